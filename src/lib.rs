@@ -0,0 +1,4769 @@
+// A grep-like tool for separated values files.
+//
+// Copyright (C) 2017-2020  Tassilo Horn <tsdh@gnu.org>
+//
+// This program is free software; you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation; either version 3 of the License, or (at your option)
+// any later version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+// more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program; if not, write to the Free Software Foundation, Inc., 51
+// Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA
+
+extern crate flate2;
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use flate2::bufread::MultiGzDecoder;
+use regex::{Regex, RegexBuilder};
+
+#[derive(Clone)]
+pub struct CSVRow {
+    pub cells: Vec<String>,
+    // The record as read, for ~ clauses.
+    pub line: String,
+    // The byte offset of the record in the input.
+    pub offset: usize,
+}
+
+// Negative columns count from the end of each row, -1 being the last.
+#[allow(clippy::upper_case_acronyms)]
+pub enum CellSelect {
+    ALL,
+    Some(Vec<isize>),
+    Except(Vec<isize>),
+    // All cells except the ones matched by a regex clause of the row.
+    Unmatched,
+}
+
+#[derive(Clone)]
+pub enum Separator {
+    Literal(String),
+    Regex(Regex),
+    // Any single one of the chars.
+    AnyOf(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    // Replaces invalid UTF-8 with U+FFFD.
+    Utf8Lossy,
+    Latin1,
+    Windows1252,
+}
+
+pub enum OutputFormat {
+    Pretty,
+    Csv,
+    Tsv,
+    Json,
+    Table,
+    NdjsonMatches,
+}
+
+// How a selected column beyond the end of a row is printed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MissingCell {
+    Placeholder,
+    Empty,
+    Skip,
+}
+
+// Which column a --header name shared by several columns refers to.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DuplicateNames {
+    First,
+    Last,
+    // The later ones are named like name_2, name_3, and so on.
+    Number,
+    Reject,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TrimSide {
+    Left,
+    Right,
+    Both,
+}
+
+// What --trim removes from the cells, whitespace unless chars are given.
+#[derive(Clone)]
+pub struct Trim {
+    pub side: TrimSide,
+    pub chars: Option<Vec<char>>,
+}
+
+pub enum CellPred {
+    Between(isize, isize),
+    InSet(HashSet<String>, bool),
+    SameAs(isize, bool),
+    IsNumber,
+    IsDate,
+    IsEmail,
+    // Missing cells count as empty.
+    IsEmpty(bool),
+}
+
+#[derive(Clone, Copy)]
+pub enum ColType {
+    Str,
+    Int,
+    Number,
+    Date,
+    Email,
+}
+
+pub struct ColumnReq {
+    col: isize,
+    kind: ColType,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AggKind {
+    Sum,
+    Min,
+    Max,
+    Avg,
+    Count,
+    Distinct,
+}
+
+// An --agg reduction of the cells in col over the selected rows.
+pub struct Aggregate {
+    kind: AggKind,
+    col: isize,
+}
+
+// A piece of an --template, either literal text or a column whose cell is
+// substituted.
+pub enum TemplatePart {
+    Text(String),
+    Col(isize),
+}
+
+// Rewrites the cell in col with Regex::replace_all before printing.
+pub struct Replacement {
+    col: isize,
+    rx: Regex,
+    replacement: String,
+}
+
+pub enum Clause {
+    // A regex matched against the cells up to the --scan-columns limit.
+    AnyCell(Regex, usize),
+    Line(Regex),
+    Cell(isize, Regex),
+    // A predicate on the cell as trimmed by the --trim.
+    Pred(isize, CellPred, Option<Trim>),
+    Not(Box<Clause>),
+}
+
+// A row matches if each element of clauses has a clause which holds.
+pub struct MatchExp {
+    clauses: Vec<Vec<Clause>>,
+    sel: CellSelect,
+    // (col, group) pairs of displayed columns showing a capture group.
+    groups: Vec<(isize, usize)>,
+    // A leading ! negates the whole expression.
+    negated: bool,
+}
+
+// The rows of a --join file by their key cell, without it.
+pub struct Join {
+    rows: HashMap<String, Vec<Vec<String>>>,
+    // The --header names of the joined cells.
+    names: Vec<String>,
+    // The number of empty cells a left join appends to unmatched rows.
+    width: usize,
+    left: bool,
+}
+
+pub struct Config {
+    pub separator: Separator,
+    pub output_separator: String,
+    pub quoting: bool,
+    pub collapse_separators: bool,
+    pub skip_blank: bool,
+    pub comment_prefix: Option<String>,
+    pub null_data: bool,
+    pub keep_bom: bool,
+    pub encoding: Encoding,
+    pub trim: Option<Trim>,
+    pub flatten: Option<String>,
+    pub profile: bool,
+    pub aggregates: Vec<Aggregate>,
+    pub group_by: Vec<isize>,
+    // The column whose distinct values --value-counts tallies.
+    pub value_counts: Option<isize>,
+    pub tee: bool,
+    pub stats: bool,
+    pub summary: bool,
+    pub progress: bool,
+    pub count: bool,
+    pub count_nonmatching: bool,
+    // Whether an input is only read up to its first selected row and no rows
+    // are printed, e.g., for listing the files with matches.
+    pub list_files: bool,
+    pub invert_match: bool,
+    // Whether a row must match all --match expressions instead of any.
+    pub match_all: bool,
+    // Whether a row matched by several expressions displays the union of
+    // their selections instead of the first one's.
+    pub union_select: bool,
+    pub require: Vec<ColumnReq>,
+    pub replacements: Vec<Replacement>,
+    pub check_columns: bool,
+    pub strict: bool,
+    // Pads rows with fill or truncates them to the expected column count.
+    pub rectangular: bool,
+    pub fill: String,
+    // None means the count of the header or first row.
+    pub column_count: Option<usize>,
+    pub resume_from: usize,
+    pub index_base: usize,
+    pub index_by_selection: bool,
+    pub no_index: bool,
+    pub no_trailing_separator: bool,
+    pub missing_cell: MissingCell,
+    pub max_cell_width: Option<usize>,
+    // Replaces the output separator inside printed cells.
+    pub sanitize_cells: Option<String>,
+    pub limit_per_group: Option<(isize, usize)>,
+    pub on_change: Option<isize>,
+    pub header: bool,
+    pub duplicate_names: DuplicateNames,
+    pub with_header: bool,
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub max_count: Option<usize>,
+    // Prints only the last n selected rows of each input.
+    pub tail: Option<usize>,
+    // Only each every-th matching row is selected, with probability sample.
+    pub every: usize,
+    pub sample: Option<f64>,
+    pub seed: u64,
+    // The column to sort the selected rows by, and whether descending.
+    pub sort: Option<(isize, bool)>,
+    pub numeric_sort: bool,
+    // Whether the --group-by groups are printed by descending or ascending
+    // number of rows instead of by their cells.
+    pub sort_by_count: Option<bool>,
+    pub jobs: usize,
+    pub from_line: usize,
+    pub to_line: Option<usize>,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub only_matching: bool,
+    pub color: bool,
+    pub output_format: OutputFormat,
+    pub json_array: bool,
+    pub json_rows: Cell<usize>,
+    pub unique: bool,
+    pub seen_rows: RefCell<HashSet<Vec<u8>>>,
+    // The prefix and cells of each row with --output-format table.
+    pub table_rows: RefCell<Vec<(String, Vec<String>)>>,
+    pub column_names: Option<Vec<String>>,
+    // The (column, new name) pairs of --rename.
+    pub renames: Vec<(String, String)>,
+    pub join: Option<Rc<Join>>,
+    // The column of the key of the joined rows.
+    pub join_col: isize,
+    pub file_name: Option<String>,
+    pub match_char_cfg: MatchCharCfg,
+    pub regex_cfg: RegexCfg,
+    pub match_vals: Vec<String>,
+    pub match_exps: Vec<MatchExp>,
+    // Like the expressions, the --template is built again once the --header
+    // names are known.
+    pub template_spec: Option<String>,
+    pub template: Option<Vec<TemplatePart>>,
+    pub column_specs: ColumnSpecs,
+}
+
+// The values of the options taking columns, which build_columns builds
+// again once the --header names are known.
+#[derive(Default)]
+pub struct ColumnSpecs {
+    pub aggregates: Vec<String>,
+    pub group_by: Option<String>,
+    pub value_counts: Option<String>,
+    pub require: Option<String>,
+    pub replacements: Vec<String>,
+    pub limit_per_group: Option<String>,
+    pub on_change: Option<String>,
+    pub sort: Option<String>,
+    // The left column of --on.
+    pub join_col: Option<String>,
+}
+
+pub type ColNames = HashMap<String, usize>;
+
+struct Profile {
+    // The --header names, if any.
+    names: Vec<String>,
+    rows: usize,
+    filled: Vec<usize>,
+    samples: Vec<Option<String>>,
+}
+
+// The --stats of the rows read and the selected ones among them.
+struct Summary {
+    read: usize,
+    selected: usize,
+    cell_counts: BTreeSet<usize>,
+    filled: Vec<usize>,
+}
+
+// The state of an Aggregate.  Distinct counts all cells, the others only
+// the numbers among them.
+struct AggState<'a> {
+    agg: &'a Aggregate,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+    numbers: usize,
+    skipped: usize,
+    distinct: HashSet<String>,
+}
+
+// Keeps the first of each --every matching rows, and then each with the
+// probability --sample.
+struct Sampler {
+    every: usize,
+    seen: usize,
+    rate: Option<f64>,
+    state: u64,
+}
+
+pub struct MatchCharCfg {
+    pub cell_select_char: String,
+    pub match_conj_char: String,
+    pub match_disj_char: String,
+    pub matches_char: String,
+    // The number of the first column in expressions, 1 with --one-based.
+    pub index_base: usize,
+    // The number of cells * clauses match against, all if None.
+    pub scan_columns: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct RegexCfg {
+    pub ignore_case: bool,
+    pub fixed_strings: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    // Whether only the regexes of <col>=<regex> clauses match whole cells.
+    pub anchor_columns: bool,
+    // What line_regexp matches ignore around the cell, like --trim does when
+    // printing.
+    pub trim: Option<Trim>,
+    // The regex crate's byte limits, its defaults if None.
+    pub size_limit: Option<usize>,
+    pub dfa_size_limit: Option<usize>,
+}
+
+// The defaults of the command line options.
+impl Default for MatchCharCfg {
+    fn default() -> MatchCharCfg {
+        MatchCharCfg {
+            cell_select_char: String::from("@"),
+            match_conj_char: String::from("&"),
+            match_disj_char: String::from("|"),
+            matches_char: String::from("="),
+            index_base: 0,
+            scan_columns: None,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            separator: Separator::Literal(String::from(";")),
+            output_separator: String::from(";"),
+            quoting: true,
+            collapse_separators: false,
+            skip_blank: false,
+            comment_prefix: None,
+            null_data: false,
+            keep_bom: false,
+            encoding: Encoding::Utf8,
+            trim: None,
+            flatten: None,
+            profile: false,
+            aggregates: vec![],
+            value_counts: None,
+            group_by: vec![],
+            tee: false,
+            stats: false,
+            summary: false,
+            progress: false,
+            count: false,
+            count_nonmatching: false,
+            list_files: false,
+            invert_match: false,
+            match_all: false,
+            union_select: false,
+            require: vec![],
+            replacements: vec![],
+            check_columns: false,
+            strict: false,
+            rectangular: false,
+            fill: String::new(),
+            column_count: None,
+            resume_from: 1,
+            index_base: 0,
+            index_by_selection: false,
+            no_index: false,
+            no_trailing_separator: false,
+            missing_cell: MissingCell::Placeholder,
+            max_cell_width: None,
+            sanitize_cells: None,
+            limit_per_group: None,
+            on_change: None,
+            header: false,
+            duplicate_names: DuplicateNames::First,
+            with_header: false,
+            line_number: false,
+            byte_offset: false,
+            max_count: None,
+            tail: None,
+            every: 1,
+            sample: None,
+            seed: 0,
+            sort: None,
+            numeric_sort: false,
+            sort_by_count: None,
+            jobs: 1,
+            from_line: 1,
+            to_line: None,
+            before_context: 0,
+            after_context: 0,
+            only_matching: false,
+            color: false,
+            output_format: OutputFormat::Pretty,
+            json_array: false,
+            json_rows: Cell::new(0),
+            unique: false,
+            seen_rows: RefCell::new(HashSet::new()),
+            table_rows: RefCell::new(vec![]),
+            column_names: None,
+            renames: vec![],
+            join: None,
+            join_col: 0,
+            file_name: None,
+            match_char_cfg: MatchCharCfg::default(),
+            regex_cfg: RegexCfg::default(),
+            match_vals: vec![],
+            match_exps: vec![],
+            template_spec: None,
+            template: None,
+            column_specs: ColumnSpecs::default(),
+        }
+    }
+}
+
+impl MatchExp {
+    fn new() -> MatchExp {
+        MatchExp {
+            clauses: vec![],
+            sel: CellSelect::ALL,
+            groups: vec![],
+            negated: false,
+        }
+    }
+
+    pub fn matches(&self, row: &CSVRow) -> bool {
+        self.negated
+            != self
+                .clauses
+                .iter()
+                .all(|alternatives| alternatives.iter().any(|clause| clause.holds(row)))
+    }
+
+    pub fn select(
+        &self,
+        row: &CSVRow,
+        row_no: Option<usize>,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let matching_sel = if config.only_matching {
+            let cells = self.matching_cells(row);
+            if cells.is_empty() {
+                return Ok(());
+            }
+            Some(CellSelect::Some(
+                cells.into_iter().map(|i| i as isize).collect(),
+            ))
+        } else {
+            self.unmatched_sel(row)
+        };
+        let sel = matching_sel.as_ref().unwrap_or(&self.sel);
+        let row = if self.groups.is_empty() {
+            Cow::Borrowed(row)
+        } else {
+            Cow::Owned(self.with_captures(row))
+        };
+        self.print_selected(row, sel, row_no, config, out)
+    }
+
+    // Prints row with the union of the selections of exps, which all match
+    // it.  Each column is displayed once at its first position in the
+    // selections of exps, in their order, and the first expression provides
+    // the highlighting.
+    fn select_union(
+        exps: &[&MatchExp],
+        row: &CSVRow,
+        row_no: Option<usize>,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let mut cols = vec![];
+        let mut shown = HashSet::new();
+        let mut captured = Cow::Borrowed(row);
+        for exp in exps {
+            let unmatched = exp.unmatched_sel(row);
+            for (col, idx) in row.displayed(unmatched.as_ref().unwrap_or(&exp.sel), config) {
+                // Missing columns are told apart by their column.
+                if shown.insert(idx.ok_or(col)) {
+                    cols.push(idx.map_or(col, |i| i as isize));
+                }
+            }
+            if !exp.groups.is_empty() {
+                let with_captures = exp.with_captures(row);
+                for idx in exp.groups.iter().filter_map(|(col, _)| row.index(*col)) {
+                    captured.to_mut().cells[idx] = with_captures.cells[idx].clone();
+                }
+            }
+        }
+        exps[0].print_selected(captured, &CellSelect::Some(cols), row_no, config, out)
+    }
+
+    // Prints the sel cells of row after the --replace replacements.
+    fn print_selected(
+        &self,
+        mut row: Cow<CSVRow>,
+        sel: &CellSelect,
+        row_no: Option<usize>,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        for r in &config.replacements {
+            let idx = match row.index(r.col) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            let replaced = match r.rx.replace_all(&row.cells[idx], r.replacement.as_str()) {
+                Cow::Owned(replaced) => replaced,
+                Cow::Borrowed(_) => continue,
+            };
+            row.to_mut().cells[idx] = replaced;
+        }
+        // A row with several multi-valued cells is reported and printed as is.
+        let flattened = match config.flatten {
+            Some(ref sub_sep) => row.flatten(sel, sub_sep).unwrap_or_else(|multi| {
+                let cols: Vec<String> = multi
+                    .iter()
+                    .map(|i| (i + config.match_char_cfg.index_base).to_string())
+                    .collect();
+                let mut err = io::stderr();
+                let _ = write_file_name(config.file_name.as_deref(), &mut err);
+                let _ = writeln!(
+                    err,
+                    "Cannot --flatten row{} with the multi-valued columns {}!",
+                    row_no.map_or(String::new(), |n| format!(" {}", n)),
+                    cols.join(", ")
+                );
+                None
+            }),
+            None => None,
+        };
+        match flattened {
+            None => row.print(sel, row_no, Some(self), config, out),
+            Some(rows) => {
+                for r in rows {
+                    r.print(sel, row_no, Some(self), config, out)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Returns row with the cells of groups replaced by the capture group of
+    // the first regex clause matching them, or by the empty string if no
+    // clause matches or the group didn't participate in the match.
+    fn with_captures(&self, row: &CSVRow) -> CSVRow {
+        let mut captured = row.clone();
+        for &(col, group) in &self.groups {
+            let idx = match row.index(col) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            let capture = self
+                .cell_rxs(row, idx)
+                .into_iter()
+                .find_map(|rx| rx.captures(&row.cells[idx]))
+                .and_then(|caps| caps.get(group).map(|m| String::from(m.as_str())));
+            captured.cells[idx] = capture.unwrap_or_default();
+        }
+        captured
+    }
+
+    // Returns the regexes of the non-negated clauses for the cell at idx of
+    // row, none if the whole expression is negated.
+    fn cell_rxs(&self, row: &CSVRow, idx: usize) -> Vec<&Regex> {
+        if self.negated {
+            return vec![];
+        }
+        self.clauses
+            .iter()
+            .flatten()
+            .filter_map(|clause| match clause {
+                Clause::Cell(col, rx) if row.index(*col) == Some(idx) => Some(rx),
+                Clause::AnyCell(rx, limit) if idx < *limit => Some(rx),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Prints the clauses and the display selection of the expression exp
+    // for --explain, with the columns counted from base.
+    pub fn explain(&self, exp: &str, base: usize, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "--match {:?}:", exp)?;
+        if self.negated {
+            writeln!(out, "  matches the rows NOT matched by")?;
+        }
+        if self.clauses.is_empty() {
+            writeln!(out, "  matches every row")?;
+        }
+        for (i, alternatives) in self.clauses.iter().enumerate() {
+            let alternatives: Vec<String> = alternatives.iter().map(|c| c.describe(base)).collect();
+            let and = if i == 0 { "  " } else { "  and " };
+            writeln!(out, "{}{}", and, alternatives.join(" or "))?;
+        }
+        let cols = |cols: &[isize]| {
+            let cols: Vec<String> = cols
+                .iter()
+                .map(|col| match self.groups.iter().find(|(c, _)| c == col) {
+                    Some((_, group)) => format!("{} (group {})", to_base(*col, base), group),
+                    None => to_base(*col, base).to_string(),
+                })
+                .collect();
+            cols.join(", ")
+        };
+        match self.sel {
+            CellSelect::ALL => writeln!(out, "  displays all cells"),
+            CellSelect::Some(ref sel) => writeln!(out, "  displays the cells {}", cols(sel)),
+            CellSelect::Except(ref sel) => {
+                writeln!(out, "  displays all cells except {}", cols(sel))
+            }
+            CellSelect::Unmatched => writeln!(out, "  displays the cells no regex clause matches"),
+        }
+    }
+
+    // Returns the selection of the cells of row not matched by a regex clause
+    // if the selection is @!.
+    fn unmatched_sel(&self, row: &CSVRow) -> Option<CellSelect> {
+        match self.sel {
+            CellSelect::Unmatched => Some(CellSelect::Except(
+                self.matching_cells(row)
+                    .into_iter()
+                    .map(|i| i as isize)
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    // Returns the sorted indices of the cells matched by a regex clause, none
+    // if the whole expression is negated.
+    fn matching_cells(&self, row: &CSVRow) -> Vec<usize> {
+        let mut cells = vec![];
+        if self.negated {
+            return cells;
+        }
+        for clause in self.clauses.iter().flatten() {
+            match clause {
+                Clause::AnyCell(rx, limit) => cells.extend(
+                    row.cells
+                        .iter()
+                        .take(*limit)
+                        .enumerate()
+                        .filter(|(_, cell)| rx.is_match(cell))
+                        .map(|(i, _)| i),
+                ),
+                Clause::Cell(col, rx) => {
+                    if let Some(idx) = row.index(*col).filter(|i| rx.is_match(&row.cells[*i])) {
+                        cells.push(idx);
+                    }
+                }
+                Clause::Line(_) | Clause::Pred(..) | Clause::Not(_) => (),
+            }
+        }
+        cells.sort_unstable();
+        cells.dedup();
+        cells
+    }
+}
+
+impl Profile {
+    fn new(names: Vec<String>) -> Profile {
+        Profile {
+            names,
+            rows: 0,
+            filled: vec![],
+            samples: vec![],
+        }
+    }
+
+    fn add(&mut self, row: &CSVRow, trim: &Option<Trim>) {
+        self.rows += 1;
+        if row.cells.len() > self.filled.len() {
+            self.filled.resize(row.cells.len(), 0);
+            self.samples.resize(row.cells.len(), None);
+        }
+        for (i, cell) in row.cells.iter().enumerate() {
+            let cell = maybe_trim(cell, trim);
+            if !cell.is_empty() {
+                self.filled[i] += 1;
+                if self.samples[i].is_none() {
+                    self.samples[i] = Some(String::from(cell));
+                }
+            }
+        }
+    }
+
+    // Labels the columns counted from base, followed by their names.
+    fn print(&self, file_name: Option<&str>, base: usize, out: &mut dyn Write) -> io::Result<()> {
+        for (i, filled) in self.filled.iter().enumerate() {
+            write_file_name(file_name, out)?;
+            write!(out, "({}) ", i + base)?;
+            if let Some(name) = self.names.get(i) {
+                write!(out, "{} ", name)?;
+            }
+            writeln!(
+                out,
+                "filled: {:.1}% sample: {}",
+                100.0 * *filled as f64 / self.rows as f64,
+                self.samples[i].as_ref().map_or("<none>", |s| s.as_str())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl AggKind {
+    fn from_name(name: &str) -> Option<AggKind> {
+        match name {
+            "sum" => Some(AggKind::Sum),
+            "min" => Some(AggKind::Min),
+            "max" => Some(AggKind::Max),
+            "avg" => Some(AggKind::Avg),
+            "count" => Some(AggKind::Count),
+            "distinct" => Some(AggKind::Distinct),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AggKind::Sum => "sum",
+            AggKind::Min => "min",
+            AggKind::Max => "max",
+            AggKind::Avg => "avg",
+            AggKind::Count => "count",
+            AggKind::Distinct => "distinct",
+        }
+    }
+}
+
+impl<'a> AggState<'a> {
+    fn new(agg: &'a Aggregate) -> AggState<'a> {
+        AggState {
+            agg,
+            sum: 0.0,
+            min: None,
+            max: None,
+            numbers: 0,
+            skipped: 0,
+            distinct: HashSet::new(),
+        }
+    }
+
+    fn add(&mut self, row: &CSVRow, trim: &Option<Trim>) {
+        if self.agg.kind == AggKind::Distinct {
+            let cell = row.cell(self.agg.col).map_or("", |c| maybe_trim(c, trim));
+            if !self.distinct.contains(cell) {
+                self.distinct.insert(String::from(cell));
+            }
+            return;
+        }
+        match row.get_number(self.agg.col) {
+            Some(n) => {
+                self.sum += n;
+                self.min = Some(self.min.map_or(n, |m| m.min(n)));
+                self.max = Some(self.max.map_or(n, |m| m.max(n)));
+                self.numbers += 1;
+            }
+            None => self.skipped += 1,
+        }
+    }
+
+    // Prints the column counted from base.
+    fn print(
+        &self,
+        file_name: Option<&str>,
+        group: Option<&str>,
+        base: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let none = || String::from("<none>");
+        let value = match self.agg.kind {
+            AggKind::Sum => self.sum.to_string(),
+            AggKind::Min => self.min.map_or_else(none, |m| m.to_string()),
+            AggKind::Max => self.max.map_or_else(none, |m| m.to_string()),
+            AggKind::Avg if self.numbers == 0 => none(),
+            AggKind::Avg => (self.sum / self.numbers as f64).to_string(),
+            AggKind::Count => self.numbers.to_string(),
+            AggKind::Distinct => self.distinct.len().to_string(),
+        };
+        write_file_name(file_name, out)?;
+        if let Some(group) = group {
+            write!(out, "{}: ", group)?;
+        }
+        write!(
+            out,
+            "{}({}) = {}",
+            self.agg.kind.name(),
+            to_base(self.agg.col, base),
+            value
+        )?;
+        if self.skipped > 0 {
+            write!(out, " ({} non-numeric cells skipped)", self.skipped)?;
+        }
+        writeln!(out)
+    }
+}
+
+impl Sampler {
+    fn new(config: &Config) -> Sampler {
+        Sampler {
+            every: config.every,
+            seen: 0,
+            rate: config.sample,
+            state: config.seed,
+        }
+    }
+
+    fn keeps(&mut self) -> bool {
+        self.seen += 1;
+        if !(self.seen - 1).is_multiple_of(self.every) {
+            return false;
+        }
+        self.rate.is_none_or(|rate| self.next_random() < rate)
+    }
+
+    // Returns a uniformly distributed number in [0, 1) using splitmix64.
+    fn next_random(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Summary {
+    fn new() -> Summary {
+        Summary {
+            read: 0,
+            selected: 0,
+            cell_counts: BTreeSet::new(),
+            filled: vec![],
+        }
+    }
+
+    fn add(&mut self, row: &CSVRow, selected: bool, trim: &Option<Trim>) {
+        self.read += 1;
+        if !selected {
+            return;
+        }
+        self.selected += 1;
+        self.cell_counts.insert(row.cells.len());
+        if row.cells.len() > self.filled.len() {
+            self.filled.resize(row.cells.len(), 0);
+        }
+        for (i, cell) in row.cells.iter().enumerate() {
+            if !maybe_trim(cell, trim).is_empty() {
+                self.filled[i] += 1;
+            }
+        }
+    }
+
+    // Labels the columns counted from base.
+    fn print(&self, file_name: Option<&str>, base: usize, out: &mut dyn Write) -> io::Result<()> {
+        write_file_name(file_name, out)?;
+        writeln!(out, "rows read: {}", self.read)?;
+        write_file_name(file_name, out)?;
+        writeln!(out, "rows selected: {}", self.selected)?;
+        if let (Some(min), Some(max)) = (self.cell_counts.first(), self.cell_counts.last()) {
+            write_file_name(file_name, out)?;
+            writeln!(
+                out,
+                "cells per row: min {}, max {}, distinct {}",
+                min,
+                max,
+                self.cell_counts.len()
+            )?;
+        }
+        for (i, filled) in self.filled.iter().enumerate() {
+            write_file_name(file_name, out)?;
+            writeln!(
+                out,
+                "({}) filled: {:.1}%",
+                i + base,
+                100.0 * *filled as f64 / self.selected as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Clause {
+    fn holds(&self, row: &CSVRow) -> bool {
+        match self {
+            Clause::AnyCell(rx, limit) => {
+                row.cells.iter().take(*limit).any(|cell| rx.is_match(cell))
+            }
+            Clause::Cell(cell_idx, rx) => row.cell(*cell_idx).is_some_and(|c| rx.is_match(c)),
+            Clause::Pred(cell_idx, pred, trim) => pred.holds(*cell_idx, row, trim),
+            Clause::Line(rx) => rx.is_match(&row.line),
+            Clause::Not(clause) => !clause.holds(row),
+        }
+    }
+
+    fn describe(&self, base: usize) -> String {
+        match self {
+            Clause::AnyCell(rx, usize::MAX) => format!("any cell matches /{}/", rx),
+            Clause::AnyCell(rx, limit) => {
+                format!("any of the first {} cells matches /{}/", limit, rx)
+            }
+            Clause::Cell(cell_idx, rx) => {
+                format!("cell {} matches /{}/", to_base(*cell_idx, base), rx)
+            }
+            Clause::Pred(cell_idx, pred, _) => {
+                format!("cell {} {}", to_base(*cell_idx, base), pred.describe(base))
+            }
+            Clause::Line(rx) => format!("the line matches /{}/", rx),
+            Clause::Not(clause) => format!("not ({})", clause.describe(base)),
+        }
+    }
+}
+
+impl CellPred {
+    fn holds(&self, cell_idx: isize, row: &CSVRow, trim: &Option<Trim>) -> bool {
+        let cell = |idx: isize| row.cell(idx).map(|c| maybe_trim(c, trim));
+        match self {
+            CellPred::Between(lo_idx, hi_idx) => {
+                match (
+                    row.get_number(cell_idx),
+                    row.get_number(*lo_idx),
+                    row.get_number(*hi_idx),
+                ) {
+                    (Some(v), Some(lo), Some(hi)) => lo <= v && v <= hi,
+                    _ => false,
+                }
+            }
+            CellPred::InSet(ref set, negated) => match cell(cell_idx) {
+                Some(cell) => set.contains(cell) != *negated,
+                None => *negated,
+            },
+            CellPred::SameAs(other_idx, negated) => match (cell(cell_idx), cell(*other_idx)) {
+                (Some(cell), Some(other)) => (cell == other) != *negated,
+                _ => false,
+            },
+            CellPred::IsNumber => row.cell(cell_idx).is_some_and(is_number),
+            CellPred::IsDate => row.cell(cell_idx).is_some_and(is_date),
+            CellPred::IsEmail => row.cell(cell_idx).is_some_and(is_email),
+            CellPred::IsEmpty(negated) => cell(cell_idx).is_none_or(str::is_empty) != *negated,
+        }
+    }
+
+    fn describe(&self, base: usize) -> String {
+        match self {
+            CellPred::Between(lo_idx, hi_idx) => format!(
+                "lies between the cells {} and {}",
+                to_base(*lo_idx, base),
+                to_base(*hi_idx, base)
+            ),
+            CellPred::InSet(ref set, negated) => {
+                let mut values: Vec<&String> = set.iter().collect();
+                values.sort_unstable();
+                let values: Vec<String> = values.into_iter().map(|v| format!("{:?}", v)).collect();
+                let which = if *negated { "none" } else { "one" };
+                format!("is {} of {}", which, values.join(", "))
+            }
+            CellPred::SameAs(other_idx, negated) => {
+                let how = if *negated { "differs from" } else { "equals" };
+                format!("{} cell {}", how, to_base(*other_idx, base))
+            }
+            CellPred::IsNumber => String::from("is a number"),
+            CellPred::IsDate => String::from("is a date"),
+            CellPred::IsEmail => String::from("is an email address"),
+            CellPred::IsEmpty(false) => String::from("is empty or missing"),
+            CellPred::IsEmpty(true) => String::from("is not empty"),
+        }
+    }
+}
+
+impl ColType {
+    fn from_name(name: &str) -> Option<ColType> {
+        match name {
+            "str" => Some(ColType::Str),
+            "int" => Some(ColType::Int),
+            "number" => Some(ColType::Number),
+            "date" => Some(ColType::Date),
+            "email" => Some(ColType::Email),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ColType::Str => "str",
+            ColType::Int => "int",
+            ColType::Number => "number",
+            ColType::Date => "date",
+            ColType::Email => "email",
+        }
+    }
+
+    fn accepts(self, cell: &str) -> bool {
+        match self {
+            ColType::Str => true,
+            ColType::Int => INT_RX.is_match(cell.trim()),
+            ColType::Number => is_number(cell),
+            ColType::Date => is_date(cell),
+            ColType::Email => is_email(cell),
+        }
+    }
+}
+
+fn is_number(cell: &str) -> bool {
+    FLOAT_RX.is_match(cell.trim())
+}
+
+fn is_email(cell: &str) -> bool {
+    EMAIL_RX.is_match(cell.trim())
+}
+
+// Accepts ISO 8601 dates like 2020-02-29, optionally followed by a time
+// like T12:30 or 12:30:59, and checks that the day exists in that month.
+fn is_date(cell: &str) -> bool {
+    match DATE_RX.captures(cell.trim()) {
+        None => false,
+        Some(caps) => {
+            let num = |i: usize| {
+                caps.get(i)
+                    .map_or(0, |m| m.as_str().parse::<u32>().unwrap())
+            };
+            let (year, month, day) = (num(1), num(2), num(3));
+            let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+            let days = match month {
+                1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+                4 | 6 | 9 | 11 => 30,
+                2 if leap => 29,
+                2 => 28,
+                _ => 0,
+            };
+            day >= 1 && day <= days && num(4) < 24 && num(5) < 60 && num(6) < 60
+        }
+    }
+}
+
+impl Encoding {
+    fn is_utf8(self) -> bool {
+        matches!(self, Encoding::Utf8 | Encoding::Utf8Lossy)
+    }
+
+    fn decode(self, bytes: Vec<u8>) -> io::Result<String> {
+        match self {
+            Encoding::Utf8 => {
+                String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Encoding::Utf8Lossy => Ok(match String::from_utf8(bytes) {
+                Ok(s) => s,
+                Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            }),
+            Encoding::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+            Encoding::Windows1252 => Ok(bytes
+                .into_iter()
+                .map(|b| match b {
+                    0x80..=0x9f => WINDOWS_1252_C1[(b - 0x80) as usize],
+                    _ => char::from(b),
+                })
+                .collect()),
+        }
+    }
+}
+
+impl Separator {
+    fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        match self {
+            Separator::Literal(sep) => line.split(sep.as_str()).collect(),
+            Separator::Regex(rx) => rx.split(line).collect(),
+            Separator::AnyOf(chars) => line.split(|c| chars.contains(c)).collect(),
+        }
+    }
+
+    // Returns the start and end of the first separator in s.
+    fn find(&self, s: &str) -> Option<(usize, usize)> {
+        match self {
+            Separator::Literal(sep) => s.find(sep.as_str()).map(|pos| (pos, pos + sep.len())),
+            Separator::Regex(rx) => rx.find(s).map(|m| (m.start(), m.end())),
+            Separator::AnyOf(chars) => s
+                .char_indices()
+                .find(|(_, c)| chars.contains(*c))
+                .map(|(pos, c)| (pos, pos + c.len_utf8())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Separator::Literal(sep) => sep,
+            Separator::Regex(rx) => rx.as_str(),
+            Separator::AnyOf(chars) => chars,
+        }
+    }
+}
+
+impl CSVRow {
+    // Returns None if line ends inside a quoted field, i.e., the record
+    // continues on the next line.
+    // With collapse, runs of separators count as one and unquoted empty
+    // cells are dropped, including at the start and end of line.
+    pub fn parse_line(
+        line: &str,
+        sep: &Separator,
+        quoting: bool,
+        collapse: bool,
+    ) -> Option<CSVRow> {
+        if !quoting || !line.contains('"') || sep.as_str().is_empty() {
+            return Some(CSVRow {
+                cells: sep
+                    .split(line)
+                    .into_iter()
+                    .filter(|cell| !collapse || !cell.is_empty())
+                    .map(String::from)
+                    .collect(),
+                line: String::from(line),
+                offset: 0,
+            });
+        }
+
+        // RFC 4180: a field starting with a double quote extends up to the
+        // next lone double quote, may contain the separator, and "" inside
+        // stands for one literal quote.  Any text between the closing quote
+        // and the next separator is kept as is.
+        let mut cells = vec![];
+        let mut cell = String::new();
+        let mut rest = line;
+        loop {
+            let quoted_cell = rest.starts_with('"');
+            if let Some(quoted) = rest.strip_prefix('"') {
+                rest = quoted;
+                loop {
+                    match rest.find('"') {
+                        None => return None,
+                        Some(pos) => {
+                            cell.push_str(&rest[..pos]);
+                            rest = &rest[pos + 1..];
+                            match rest.strip_prefix('"') {
+                                Some(r) => {
+                                    cell.push('"');
+                                    rest = r;
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                }
+            }
+            let next = sep.find(rest);
+            cell.push_str(&rest[..next.map_or(rest.len(), |(start, _)| start)]);
+            if !collapse || quoted_cell || !cell.is_empty() {
+                cells.push(cell);
+            }
+            match next {
+                None => break,
+                Some((_, end)) => {
+                    cell = String::new();
+                    rest = &rest[end..];
+                }
+            }
+        }
+        Some(CSVRow {
+            cells,
+            line: String::from(line),
+            offset: 0,
+        })
+    }
+
+    pub fn get_cell(&self, idx: usize) -> Option<&str> {
+        if idx >= self.cells.len() {
+            None
+        } else {
+            Some(self.cells[idx].as_str())
+        }
+    }
+
+    // Resolves col to an index into cells, counting negative cols from the
+    // end, or None if the row has no such cell.
+    fn index(&self, col: isize) -> Option<usize> {
+        let idx = if col < 0 {
+            self.cells.len().checked_sub(col.unsigned_abs())?
+        } else {
+            col as usize
+        };
+        Some(idx).filter(|i| *i < self.cells.len())
+    }
+
+    fn cell(&self, col: isize) -> Option<&str> {
+        self.index(col).map(|i| self.cells[i].as_str())
+    }
+
+    fn get_number(&self, col: isize) -> Option<f64> {
+        self.cell(col).and_then(|c| c.trim().parse::<f64>().ok())
+    }
+
+    fn selected_indices(&self, cols: &CellSelect) -> Vec<usize> {
+        match cols {
+            // Only the header is printed with an unresolved @!.
+            CellSelect::ALL | CellSelect::Unmatched => (0..self.cells.len()).collect(),
+            CellSelect::Except(ref cols) => (0..self.cells.len())
+                .filter(|i| !cols.iter().any(|c| self.index(*c) == Some(*i)))
+                .collect(),
+            CellSelect::Some(ref cols) => cols.iter().filter_map(|c| self.index(*c)).collect(),
+        }
+    }
+
+    // Returns the displayed columns with their index into cells, which is
+    // None for missing ones unless --missing-cell skip drops them.
+    fn displayed(&self, cols: &CellSelect, config: &Config) -> Vec<(isize, Option<usize>)> {
+        match cols {
+            CellSelect::Some(ref cols) => cols
+                .iter()
+                .map(|c| (*c, self.index(*c)))
+                .filter(|(_, idx)| idx.is_some() || config.missing_cell != MissingCell::Skip)
+                .collect(),
+            _ => self
+                .selected_indices(cols)
+                .into_iter()
+                .map(|i| (i as isize, Some(i)))
+                .collect(),
+        }
+    }
+
+    // Returns one row per element of the single selected cell containing
+    // sub_sep, or None if no selected cell is multi-valued.
+    fn flatten(&self, cols: &CellSelect, sub_sep: &str) -> Result<Option<Vec<CSVRow>>, Vec<usize>> {
+        let mut multi: Vec<usize> = self
+            .selected_indices(cols)
+            .into_iter()
+            .filter(|i| self.cells[*i].contains(sub_sep))
+            .collect();
+        multi.sort_unstable();
+        multi.dedup();
+        match multi.len() {
+            0 => Ok(None),
+            1 => Ok(Some(
+                self.cells[multi[0]]
+                    .split(sub_sep)
+                    .map(|v| {
+                        let mut r = self.clone();
+                        r.cells[multi[0]] = String::from(v);
+                        r
+                    })
+                    .collect(),
+            )),
+            _ => Err(multi),
+        }
+    }
+
+    // Prints the cells selected by cols, prefixed with row_no if given and
+    // --line-number is set.  With --color, the matches of the regexes of exp
+    // are highlighted.
+    fn print(
+        &self,
+        cols: &CellSelect,
+        row_no: Option<usize>,
+        exp: Option<&MatchExp>,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        // The cells as printed without prefixes are the key for --unique.
+        let mut cells = vec![];
+        match config.output_format {
+            _ if config.template.is_some() => self.print_template(config, &mut cells)?,
+            OutputFormat::Pretty => self.print_pretty(cols, exp, config, &mut cells)?,
+            OutputFormat::Csv => {
+                self.print_separated(cols, &config.output_separator, config, &mut cells)?
+            }
+            OutputFormat::Tsv => self.print_separated(cols, "\t", config, &mut cells)?,
+            OutputFormat::Json => self.print_json(cols, config, &mut cells)?,
+            // Only the --unique key, the rows are printed by print_table.
+            OutputFormat::Table => self.print_separated(cols, "\t", config, &mut cells)?,
+            OutputFormat::NdjsonMatches => self.print_matches(row_no, exp, config, &mut cells)?,
+        }
+        if config.unique {
+            let mut seen = config.seen_rows.borrow_mut();
+            if seen.contains(&cells) {
+                return Ok(());
+            }
+            seen.insert(cells.clone());
+        }
+        if let OutputFormat::Table = config.output_format {
+            let mut prefix = vec![];
+            write_file_name(config.file_name.as_deref(), &mut prefix)?;
+            if let Some(row_no) = row_no.filter(|_| config.line_number) {
+                write!(prefix, "{}:", row_no)?;
+            }
+            if row_no.is_some() && config.byte_offset {
+                write!(prefix, "{}:", self.offset)?;
+            }
+            let cells = self
+                .displayed(cols, config)
+                .into_iter()
+                .map(|(col, idx)| match idx {
+                    None => missing_cell(col, config).unwrap_or_default(),
+                    Some(i) => {
+                        let cell = display_cell(&self.cells[i], config);
+                        sanitize_cell(cell, &config.output_separator, config).into_owned()
+                    }
+                })
+                .collect();
+            let prefix = String::from_utf8_lossy(&prefix).into_owned();
+            config.table_rows.borrow_mut().push((prefix, cells));
+            return Ok(());
+        }
+        if let OutputFormat::NdjsonMatches = config.output_format {
+            out.write_all(&cells)?;
+            return out.write_all(&[line_end(config.null_data)]);
+        }
+        if let OutputFormat::Json = config.output_format {
+            // With --json-array, main closes the array after the last row.
+            let rows = config.json_rows.get();
+            config.json_rows.set(rows + 1);
+            match (config.json_array, rows) {
+                (false, _) => (),
+                (true, 0) => write!(out, "[")?,
+                (true, _) => writeln!(out, ",")?,
+            }
+            out.write_all(&cells)?;
+            return if config.json_array {
+                Ok(())
+            } else {
+                out.write_all(&[line_end(config.null_data)])
+            };
+        }
+        write_file_name(config.file_name.as_deref(), out)?;
+        if let Some(row_no) = row_no.filter(|_| config.line_number) {
+            write!(out, "{}:", row_no)?;
+        }
+        if row_no.is_some() && config.byte_offset {
+            write!(out, "{}:", self.offset)?;
+        }
+        out.write_all(&cells)?;
+        out.write_all(&[line_end(config.null_data)])
+    }
+
+    // Prints a JSON object with the file name, the row number, all cells as
+    // read, and the byte ranges within them matched by the regexes of exp.
+    fn print_matches(
+        &self,
+        row_no: Option<usize>,
+        exp: Option<&MatchExp>,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let mut spans = vec![];
+        if let Some(exp) = exp {
+            for (i, cell) in self.cells.iter().enumerate() {
+                for rx in exp.cell_rxs(self, i) {
+                    spans.extend(rx.find_iter(cell).map(|m| (i, m.start(), m.end())));
+                }
+            }
+        }
+        spans.sort_unstable();
+        spans.dedup();
+        let cells: Vec<String> = self.cells.iter().map(|c| json_string(c)).collect();
+        let matches: Vec<String> = spans
+            .into_iter()
+            .map(|(col, start, end)| {
+                format!("{{\"col\":{},\"start\":{},\"end\":{}}}", col, start, end)
+            })
+            .collect();
+        write!(out, "{{")?;
+        if let Some(ref file_name) = config.file_name {
+            write!(out, "\"file\":{},", json_string(file_name))?;
+        }
+        write!(
+            out,
+            "\"row\":{},\"cells\":[{}],\"matches\":[{}]}}",
+            row_no.map_or(String::from("null"), |n| n.to_string()),
+            cells.join(","),
+            matches.join(",")
+        )
+    }
+
+    // Prints the cells as JSON object keyed by the --header names, or else as
+    // array.  Missing selected cells are null or the placeholder string.
+    fn print_json(
+        &self,
+        cols: &CellSelect,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let displayed = self.displayed(cols, config);
+        let value = |col: isize, idx: Option<usize>| match idx {
+            None => missing_cell(col, config).map_or(String::from("null"), |p| json_string(&p)),
+            Some(i) => {
+                let cell = display_cell(&self.cells[i], config);
+                json_string(&sanitize_cell(cell, &config.output_separator, config))
+            }
+        };
+        let json = match config.column_names {
+            None => {
+                let values: Vec<String> = displayed.into_iter().map(|(c, i)| value(c, i)).collect();
+                format!("[{}]", values.join(","))
+            }
+            Some(ref names) => {
+                let members: Vec<String> = displayed
+                    .into_iter()
+                    .map(|(col, idx)| {
+                        let name = idx
+                            .and_then(|i| names.get(i))
+                            .cloned()
+                            .unwrap_or_else(|| col.to_string());
+                        format!("{}:{}", json_string(&name), value(col, idx))
+                    })
+                    .collect();
+                format!("{{{}}}", members.join(","))
+            }
+        };
+        write!(out, "{}", json)
+    }
+
+    // Prints (i) labels before the cells, or with no_index only the cells
+    // joined by the output separator.
+    fn print_pretty(
+        &self,
+        cols: &CellSelect,
+        exp: Option<&MatchExp>,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        // Sanitized before highlighting, whose escapes may hold the separator.
+        let cell = |i: usize| {
+            let cell = display_cell(&self.cells[i], config);
+            let cell = sanitize_cell(cell, &config.output_separator, config);
+            match exp.filter(|_| config.color) {
+                Some(exp) => Cow::Owned(highlight(&cell, &exp.cell_rxs(self, i)).into_owned()),
+                None => cell,
+            }
+        };
+        if config.no_index {
+            for (pos, (col, idx)) in self.displayed(cols, config).into_iter().enumerate() {
+                if pos > 0 {
+                    out.write_all(config.output_separator.as_bytes())?;
+                }
+                match idx {
+                    None => write!(out, "{}", missing_cell(col, config).unwrap_or_default())?,
+                    Some(i) => write!(out, "{}", cell(i))?,
+                }
+            }
+            return Ok(());
+        }
+        // Only the cells of a Some selection are followed by the separator.
+        let sep = match cols {
+            CellSelect::Some(_) => format!("{} ", config.output_separator),
+            _ => String::from(" "),
+        };
+        for (pos, (col, idx)) in self.displayed(cols, config).into_iter().enumerate() {
+            if pos > 0 && config.no_trailing_separator {
+                out.write_all(sep.as_bytes())?;
+            }
+            match idx {
+                None => write!(out, "{}", missing_cell(col, config).unwrap_or_default())?,
+                Some(i) => {
+                    let label = if config.index_by_selection { pos } else { i };
+                    write!(out, "({}) {}", label + config.index_base, cell(i))?;
+                }
+            }
+            if !config.no_trailing_separator {
+                out.write_all(sep.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Prints the --template with its columns replaced by their cells.
+    fn print_template(&self, config: &Config, out: &mut dyn Write) -> io::Result<()> {
+        for part in config.template.iter().flatten() {
+            match part {
+                TemplatePart::Text(text) => out.write_all(text.as_bytes())?,
+                TemplatePart::Col(col) => match self.index(*col) {
+                    Some(i) => {
+                        let cell = display_cell(&self.cells[i], config);
+                        write!(
+                            out,
+                            "{}",
+                            sanitize_cell(cell, &config.output_separator, config)
+                        )?
+                    }
+                    None => write!(out, "{}", missing_cell(*col, config).unwrap_or_default())?,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Prints the cells as one separated values record which reads back as
+    // the same cells.
+    fn print_separated(
+        &self,
+        cols: &CellSelect,
+        sep: &str,
+        config: &Config,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        for (pos, (col, idx)) in self.displayed(cols, config).into_iter().enumerate() {
+            if pos > 0 {
+                write!(out, "{}", sep)?;
+            }
+            let cell = match idx {
+                None => missing_cell(col, config).unwrap_or_default(),
+                Some(i) => display_cell(&self.cells[i], config).into_owned(),
+            };
+            let cell = sanitize_cell(Cow::Owned(cell), sep, config);
+            write!(out, "{}", quote_cell(&cell, sep))?;
+        }
+        Ok(())
+    }
+}
+
+// Returns cell as printed, i.e., trimmed and cut after --max-cell-width
+// chars.
+fn display_cell<'a>(cell: &'a str, config: &Config) -> Cow<'a, str> {
+    let cell = maybe_trim(cell, &config.trim);
+    match config
+        .max_cell_width
+        .and_then(|w| cell.char_indices().nth(w))
+    {
+        Some((end, _)) => Cow::Owned(format!("{}\u{2026}", &cell[..end])),
+        None => Cow::Borrowed(cell),
+    }
+}
+
+// Returns cell with each sep replaced by the --sanitize-cells replacement.
+fn sanitize_cell<'a>(cell: Cow<'a, str>, sep: &str, config: &Config) -> Cow<'a, str> {
+    match config.sanitize_cells {
+        Some(ref replacement) if !sep.is_empty() && cell.contains(sep) => {
+            Cow::Owned(cell.replace(sep, replacement))
+        }
+        _ => cell,
+    }
+}
+
+// Converts the 0-based column col to the one counted from base.
+fn to_base(col: isize, base: usize) -> isize {
+    if col < 0 {
+        col
+    } else {
+        col + base as isize
+    }
+}
+
+// Returns the text printed for the missing column col, or None if it's
+// printed empty.
+fn missing_cell(col: isize, config: &Config) -> Option<String> {
+    match config.missing_cell {
+        MissingCell::Placeholder => Some(format!(
+            "<no col {}>",
+            to_base(col, config.match_char_cfg.index_base)
+        )),
+        _ => None,
+    }
+}
+
+// Prints and forgets the rows collected with --output-format table, the
+// cells padded to the widest one in their column.
+fn print_table(config: &Config, out: &mut dyn Write) -> io::Result<()> {
+    let rows = config.table_rows.replace(vec![]);
+    let prefix_width = rows
+        .iter()
+        .map(|(p, _)| display_width(p))
+        .max()
+        .unwrap_or(0);
+    let mut widths: Vec<usize> = vec![];
+    for (_, cells) in &rows {
+        if cells.len() > widths.len() {
+            widths.resize(cells.len(), 0);
+        }
+        for (i, cell) in cells.iter().enumerate() {
+            widths[i] = widths[i].max(display_width(cell));
+        }
+    }
+    for (prefix, cells) in &rows {
+        let mut line = String::from(prefix.as_str());
+        pad(&mut line, prefix_width - display_width(prefix));
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                line.push_str(TABLE_GAP);
+            }
+            line.push_str(cell);
+            if i + 1 < cells.len() {
+                pad(&mut line, widths[i] - display_width(cell));
+            }
+        }
+        out.write_all(line.trim_end_matches(' ').as_bytes())?;
+        out.write_all(&[line_end(config.null_data)])?;
+    }
+    Ok(())
+}
+
+fn pad(s: &mut String, n: usize) {
+    s.extend(std::iter::repeat_n(' ', n));
+}
+
+// Returns the number of terminal columns s takes, counting East Asian wide
+// chars twice and combining marks and control chars not at all.
+fn display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| match c as u32 {
+            0..=0x1f | 0x7f..=0x9f => 0,
+            0x300..=0x36f | 0x200b..=0x200f | 0x20d0..=0x20ff | 0xfe00..=0xfe0f => 0,
+            0xfe20..=0xfe2f => 0,
+            0x1100..=0x115f | 0x2e80..=0x303e | 0x3041..=0x33ff | 0x3400..=0x4dbf => 2,
+            0x4e00..=0x9fff | 0xa000..=0xa4cf | 0xac00..=0xd7a3 | 0xf900..=0xfaff => 2,
+            0xfe30..=0xfe4f | 0xff00..=0xff60 | 0xffe0..=0xffe6 => 2,
+            0x1f300..=0x1f64f | 0x1f900..=0x1f9ff | 0x20000..=0x2fffd | 0x30000..=0x3fffd => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+// Wraps the matches of rxs in cell in MATCH_COLOR like grep --color.
+fn highlight<'a>(cell: &'a str, rxs: &[&Regex]) -> Cow<'a, str> {
+    let mut spans: Vec<(usize, usize)> = rxs
+        .iter()
+        .flat_map(|rx| rx.find_iter(cell))
+        .filter(|m| !m.as_str().is_empty())
+        .map(|m| (m.start(), m.end()))
+        .collect();
+    if spans.is_empty() {
+        return Cow::Borrowed(cell);
+    }
+    // Overlapping matches are merged into one highlighted span.
+    spans.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = vec![];
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    let mut result = String::with_capacity(cell.len() + merged.len() * 16);
+    let mut pos = 0;
+    for (start, end) in merged {
+        result.push_str(&cell[pos..start]);
+        result.push_str(MATCH_COLOR);
+        result.push_str(&cell[start..end]);
+        result.push_str(COLOR_END);
+        pos = end;
+    }
+    result.push_str(&cell[pos..]);
+    Cow::Owned(result)
+}
+
+// Encloses cell in double quotes if it contains sep, a quote, or a line
+// break (RFC 4180).
+fn quote_cell<'a>(cell: &'a str, sep: &str) -> Cow<'a, str> {
+    if (!sep.is_empty() && cell.contains(sep)) || cell.contains(['"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", cell.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(cell)
+    }
+}
+
+lazy_static! {
+    static ref PRED_RX: Regex =
+        Regex::new(r"^([^:]+):([a-z-]+)(?:\((.*)\))?$").expect("Invalid Regex in the code!");
+    static ref INT_RX: Regex = Regex::new(r"^[+-]?\d+$").expect("Invalid Regex in the code!");
+    static ref FLOAT_RX: Regex =
+        Regex::new(r"^[+-]?(\d+\.?\d*|\.\d+)([eE][+-]?\d+)?$").expect("Invalid Regex in the code!");
+    static ref DATE_RX: Regex =
+        Regex::new(r"^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2})(?::(\d{2}))?)?$")
+            .expect("Invalid Regex in the code!");
+    static ref EMAIL_RX: Regex =
+        Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s.]+$").expect("Invalid Regex in the code!");
+    static ref ASTERISK_RX: Regex =
+        Regex::new([r"^", regex::escape("*").as_str(), "$"].join("").as_ref())
+            .expect("Invalid Regex in the code!");
+}
+
+const IN_PREFIX: &str = "in:";
+const LINE_COL: &str = "~";
+const NOT_IN_PREFIX: &str = "notin:";
+
+fn maybe_trim<'a>(cell: &'a str, trim: &Option<Trim>) -> &'a str {
+    match trim {
+        None => cell,
+        Some(trim) => {
+            let trimmed = |c: char| {
+                trim.chars
+                    .as_ref()
+                    .map_or(c.is_whitespace(), |cs| cs.contains(&c))
+            };
+            match trim.side {
+                TrimSide::Left => cell.trim_start_matches(trimmed),
+                TrimSide::Right => cell.trim_end_matches(trimmed),
+                TrimSide::Both => cell.trim_matches(trimmed),
+            }
+        }
+    }
+}
+
+// The input lines, or records with --null-data, with the byte offsets at
+// which they start.
+pub type InputLines = Box<dyn Iterator<Item = io::Result<(usize, Line)>>>;
+
+// A line as read, which is only decoded once its text is needed.
+pub struct Line {
+    bytes: Vec<u8>,
+    encoding: Encoding,
+}
+
+impl Line {
+    fn text(self) -> io::Result<String> {
+        self.encoding.decode(self.bytes)
+    }
+}
+
+pub fn line_iter(
+    file_name: Option<&str>,
+    buffer_size: usize,
+    decompress: bool,
+    null_data: bool,
+    keep_bom: bool,
+    encoding: Encoding,
+    follow: bool,
+) -> io::Result<InputLines> {
+    let mut reader: Box<dyn BufRead + Send> = match file_name {
+        None => Box::new(BufReader::with_capacity(buffer_size, io::stdin())),
+        Some(filename) if follow => Box::new(BufReader::with_capacity(
+            buffer_size,
+            Follow::open(filename)?,
+        )),
+        Some(filename) => Box::new(BufReader::with_capacity(buffer_size, File::open(filename)?)),
+    };
+    if decompress
+        && (file_name.is_some_and(|f| f.ends_with(".gz"))
+            || reader.fill_buf()?.starts_with(GZIP_MAGIC))
+    {
+        // Like gzip -dc, concatenated members are decompressed one after another.
+        reader = Box::new(BufReader::with_capacity(
+            buffer_size,
+            MultiGzDecoder::new(reader),
+        ));
+    }
+    let mut offset = 0;
+    if !keep_bom && encoding.is_utf8() {
+        offset += skip_bom(&mut reader)?;
+    }
+    Ok(split_lines(reader, null_data, encoding, offset))
+}
+
+// Returns the NUL-terminated records of reader with --null-data, else its
+// lines, the first one starting at offset.
+fn split_lines(
+    mut reader: Box<dyn BufRead>,
+    null_data: bool,
+    encoding: Encoding,
+    mut offset: usize,
+) -> InputLines {
+    let end = line_end(null_data);
+    Box::new(iter::from_fn(move || {
+        let mut bytes = vec![];
+        match reader.read_until(end, &mut bytes) {
+            Ok(0) => None,
+            Err(e) => Some(Err(e)),
+            Ok(n) => {
+                let start = offset;
+                offset += n;
+                if bytes.last() == Some(&end) {
+                    bytes.pop();
+                }
+                if !null_data && bytes.last() == Some(&b'\r') {
+                    bytes.pop();
+                }
+                Some(Ok((start, Line { bytes, encoding })))
+            }
+        }
+    }))
+}
+
+// Consumes a leading UTF-8 byte order mark and returns its length.
+fn skip_bom(reader: &mut dyn BufRead) -> io::Result<usize> {
+    if reader.fill_buf()?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        return Ok(UTF8_BOM.len());
+    }
+    Ok(0)
+}
+
+fn line_end(null_data: bool) -> u8 {
+    if null_data {
+        b'\0'
+    } else {
+        b'\n'
+    }
+}
+
+// Reads a file like tail -f: at its end, it waits for appended data instead
+// of ending, so a partially written last line is only read once complete.
+// A file shrunk by truncation, or replaced by another one at its path as
+// on rotation, is read again from its start.
+struct Follow {
+    path: PathBuf,
+    file: File,
+    pos: u64,
+}
+
+impl Follow {
+    fn open(path: &str) -> io::Result<Follow> {
+        Ok(Follow {
+            path: PathBuf::from(path),
+            file: File::open(path)?,
+            pos: 0,
+        })
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            thread::sleep(FOLLOW_INTERVAL);
+            // A missing file may be about to be recreated.
+            if let Ok(meta) = fs::metadata(&self.path) {
+                if meta.len() < self.pos || !same_file(&meta, &self.file.metadata()?) {
+                    self.file = File::open(&self.path)?;
+                    self.pos = 0;
+                }
+            }
+        }
+    }
+}
+
+// Whether a and b belong to the same file, which is only known on Unix.
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
+
+// Iterates the records of the input.  With quoting, a record continues on
+// the next physical line as long as a quoted field is still open.
+struct Records {
+    lines: InputLines,
+    line_end: char,
+    separator: Separator,
+    quoting: bool,
+    collapse: bool,
+    skip_blank: bool,
+    trim: Option<Trim>,
+    comment_prefix: Option<String>,
+    line_no: usize,
+    quoted: bool,
+    join: Option<Rc<Join>>,
+    join_col: isize,
+    // Whether the next record is the --header joined with the join's one.
+    header_next: bool,
+    joined: VecDeque<CSVRow>,
+    // The number of rows left before --to-line, if any.
+    remaining: Option<usize>,
+}
+
+impl Records {
+    fn new(lines: InputLines, config: &Config) -> Records {
+        Records {
+            lines,
+            line_end: char::from(line_end(config.null_data)),
+            separator: config.separator.clone(),
+            quoting: config.quoting,
+            collapse: config.collapse_separators,
+            skip_blank: config.skip_blank,
+            trim: config.trim.clone(),
+            comment_prefix: config.comment_prefix.clone(),
+            line_no: 0,
+            quoted: false,
+            join: config.join.clone(),
+            join_col: config.join_col,
+            header_next: config.header,
+            joined: VecDeque::new(),
+            remaining: None,
+        }
+    }
+
+    // Skips n rows without matching them, e.g., for --resume-from-line and
+    // --from-line.  Only lines which may start a quoted field spanning lines
+    // are decoded, unless --join, --skip-blank, or --comment-char need all.
+    fn skip_records(&mut self, n: usize) -> io::Result<()> {
+        let raw = self.join.is_none() && !self.skip_blank && self.comment_prefix.is_none();
+        for _ in 0..n {
+            if !raw {
+                if self.next().transpose()?.is_none() {
+                    break;
+                }
+                continue;
+            }
+            if self.remaining == Some(0) {
+                break;
+            }
+            let (offset, line) = match self.lines.next() {
+                Some(line) => line.map_err(read_error)?,
+                None => break,
+            };
+            self.line_no += 1;
+            self.remaining = self.remaining.map(|n| n - 1);
+            if self.quoting && line.bytes.contains(&b'"') {
+                self.parse_record(offset, line.text().map_err(read_error)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Whether line is dropped by --skip-blank or --comment-char.  Lines
+    // continuing a quoted field are never dropped.
+    fn skips(&self, line: &str) -> bool {
+        (self.skip_blank && maybe_trim(line, &self.trim).is_empty())
+            || self
+                .comment_prefix
+                .as_ref()
+                .is_some_and(|prefix| line.starts_with(prefix.as_str()))
+    }
+}
+
+impl Iterator for Records {
+    type Item = io::Result<CSVRow>;
+
+    // With --join, returns the joined rows of each record.
+    fn next(&mut self) -> Option<io::Result<CSVRow>> {
+        match self.remaining {
+            Some(0) => return None,
+            Some(ref mut n) => *n -= 1,
+            None => (),
+        }
+        let join = match self.join {
+            None => return self.next_record().transpose(),
+            Some(ref join) => Rc::clone(join),
+        };
+        loop {
+            if let Some(row) = self.joined.pop_front() {
+                return Some(Ok(row));
+            }
+            let mut row = match self.next_record() {
+                Ok(row) => row?,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.header_next {
+                self.header_next = false;
+                row.cells.extend(join.names.iter().cloned());
+                return Some(Ok(row));
+            }
+            self.joined.extend(join.rows(row, self.join_col));
+        }
+    }
+}
+
+impl Records {
+    fn next_record(&mut self) -> io::Result<Option<CSVRow>> {
+        let (offset, record) = loop {
+            let (offset, line) = match self.lines.next() {
+                Some(line) => read_line(line)?,
+                None => return Ok(None),
+            };
+            self.line_no += 1;
+            if !self.skips(&line) {
+                break (offset, line);
+            }
+        };
+        self.parse_record(offset, record).map(Some)
+    }
+
+    // Parses the record whose first line is record, reading the following
+    // lines as long as a quoted field is open.
+    fn parse_record(&mut self, offset: usize, mut record: String) -> io::Result<CSVRow> {
+        let start = self.line_no;
+        loop {
+            if let Some(mut row) =
+                CSVRow::parse_line(&record, &self.separator, self.quoting, self.collapse)
+            {
+                self.quoted = self.quoted || (self.quoting && record.contains('"'));
+                row.offset = offset;
+                return Ok(row);
+            }
+            match self.lines.next() {
+                None => {
+                    return Err(input_error(format!(
+                        "Unterminated quoted field in the record starting at line {}!",
+                        start
+                    )))
+                }
+                Some(line) => {
+                    record.push(self.line_end);
+                    record.push_str(&read_line(line)?.1);
+                    self.line_no += 1;
+                }
+            }
+        }
+    }
+}
+
+fn read_line(line: io::Result<(usize, Line)>) -> io::Result<(usize, String)> {
+    let (offset, line) = line.map_err(read_error)?;
+    Ok((offset, line.text().map_err(read_error)?))
+}
+
+fn read_error(e: io::Error) -> io::Error {
+    input_error(format!("Cannot read input: {}", e))
+}
+
+// Overwrites the progress line on stderr, which a last one ends.  The rows
+// printed since the previous one are flushed onto the erased line so that
+// they don't follow the progress on a terminal.
+fn write_progress(
+    file_name: Option<&str>,
+    read: usize,
+    selected: usize,
+    last: bool,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut err = io::stderr();
+    write!(err, "\r{}", CLEAR_LINE)?;
+    out.flush()?;
+    write_file_name(file_name, &mut err)?;
+    write!(err, "{} rows read, {} selected", read, selected)?;
+    if last {
+        writeln!(err)
+    } else {
+        err.flush()
+    }
+}
+
+// Prints the selected row once, with the selection of the first matching
+// expression, or of the first one if no or all must match.
+fn print_selected_row(
+    row: &CSVRow,
+    row_no: usize,
+    matched: &[bool],
+    match_exps: &[MatchExp],
+    config: &Config,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if config.invert_match || config.match_all {
+        return match_exps[0].select(row, Some(row_no), config, out);
+    }
+    let matching: Vec<&MatchExp> = match_exps
+        .iter()
+        .zip(matched)
+        .filter(|(_, m)| **m)
+        .map(|(exp, _)| exp)
+        .collect();
+    if config.union_select {
+        MatchExp::select_union(&matching, row, Some(row_no), config, out)
+    } else {
+        matching[0].select(row, Some(row_no), config, out)
+    }
+}
+
+// Prints the --value-counts as value<TAB>count lines, the most frequent
+// first and values with the same count in their order.
+fn print_value_counts(
+    counts: HashMap<String, usize>,
+    file_name: Option<&str>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (value, count) in counts {
+        write_file_name(file_name, out)?;
+        writeln!(out, "{}\t{}", value, count)?;
+    }
+    Ok(())
+}
+
+fn write_file_name(file_name: Option<&str>, out: &mut dyn Write) -> io::Result<()> {
+    match file_name {
+        Some(name) => write!(out, "{}:", name),
+        None => Ok(()),
+    }
+}
+
+// With --rectangular, pads row with the --fill or truncates it to the
+// column_count, which the first row sets if unknown.
+fn make_rectangular(row: &mut CSVRow, column_count: &mut Option<usize>, config: &Config) {
+    if config.rectangular {
+        let width = *column_count.get_or_insert(row.cells.len());
+        row.cells.resize(width, config.fill.clone());
+    }
+}
+
+// Prints a -- line if row_no doesn't directly follow the last printed row.
+fn write_group_sep(
+    last_row_no: &mut Option<usize>,
+    row_no: usize,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    if last_row_no.is_some_and(|last| last + 1 < row_no) {
+        writeln!(out, "--")?;
+    }
+    *last_row_no = Some(row_no);
+    Ok(())
+}
+
+// What svgrep_lines found in one input.
+pub struct Stats {
+    pub matched: bool,
+    pub violations: usize,
+}
+
+// Greps the rows read from reader into out.  With --header, the expressions
+// are built from config.match_vals, otherwise config.match_exps are used.
+pub fn svgrep<R: BufRead + 'static>(
+    reader: R,
+    config: &mut Config,
+    out: &mut dyn Write,
+) -> io::Result<Stats> {
+    let mut reader: Box<dyn BufRead> = Box::new(reader);
+    let mut offset = 0;
+    if !config.keep_bom && config.encoding.is_utf8() {
+        offset = skip_bom(&mut reader)?;
+    }
+    let lines = split_lines(reader, config.null_data, config.encoding, offset);
+    svgrep_lines(lines, config, out)
+}
+
+pub fn svgrep_lines(
+    lines: InputLines,
+    config: &mut Config,
+    out: &mut dyn Write,
+) -> io::Result<Stats> {
+    let mut records = Records::new(lines, config);
+    let mut header_row = None;
+    if config.header {
+        if let Some(mut header) = records.next().transpose()? {
+            let names = col_names(&header, config.duplicate_names).map_err(input_error)?;
+            config.match_exps = build_match_exps(
+                &config.match_vals,
+                &config.match_char_cfg,
+                &config.regex_cfg,
+                Some(&names),
+            )
+            .map_err(input_errors)?;
+            config.build_columns(Some(&names)).map_err(input_errors)?;
+            records.join_col = config.join_col;
+            // Only the printed names change, expressions use the read ones.
+            for (col, name) in &config.renames {
+                match col_index(col, config.match_char_cfg.index_base, Some(&names))
+                    .map(|c| header.index(c))
+                {
+                    Ok(Some(idx)) => header.cells[idx] = name.clone(),
+                    Ok(None) => {
+                        return Err(input_error(format!(
+                            "There is no column {} to --rename!",
+                            col
+                        )))
+                    }
+                    Err(msg) => return Err(input_error(msg)),
+                }
+            }
+            if let Some(ref spec) = config.template_spec {
+                config.template = Some(
+                    build_template(spec, config.match_char_cfg.index_base, Some(&names))
+                        .map_err(input_error)?,
+                );
+            }
+            config.column_names = Some(
+                header
+                    .cells
+                    .iter()
+                    .map(|n| String::from(n.trim()))
+                    .collect(),
+            );
+            header_row = Some(header);
+        }
+    }
+    // Like --line-number, --resume-from-line and --from-line count the rows
+    // after the header.  The skipped ones aren't even matched, and reading
+    // ends after --to-line.
+    let first_row_no = config.resume_from.max(config.from_line);
+    records.skip_records(first_row_no - 1)?;
+    records.remaining = config
+        .to_line
+        .map(|to| (to + 1).saturating_sub(first_row_no));
+
+    let all_match = &vec![MatchExp::new()];
+    let match_exps = if config.match_exps.is_empty() {
+        all_match
+    } else {
+        &config.match_exps
+    };
+
+    let shows_rows = config.tee
+        || !config.count
+            && !config.count_nonmatching
+            && !config.profile
+            && config.aggregates.is_empty()
+            && config.value_counts.is_none()
+            && config.require.is_empty()
+            && !config.check_columns;
+    if let Some(ref header) = header_row {
+        let is_json = matches!(
+            config.output_format,
+            OutputFormat::Json | OutputFormat::NdjsonMatches
+        );
+        if config.with_header && shows_rows && !is_json {
+            header.print(&match_exps[0].sel, None, None, config, out)?;
+        }
+    }
+
+    let config: &Config = config;
+    let column_count = config
+        .column_count
+        .or_else(|| header_row.as_ref().map(|h| h.cells.len()));
+    if config.jobs > 1 {
+        thread::scope(|scope| {
+            let mut rows = ParMatches::new(scope, records, match_exps, config.jobs);
+            select_rows(
+                &mut rows,
+                first_row_no,
+                column_count,
+                header_row.as_ref(),
+                match_exps,
+                config,
+                out,
+            )
+        })
+    } else {
+        let mut rows = records.map(|row| {
+            row.map(|row| {
+                let matched = match_exps.iter().map(|e| e.matches(&row)).collect();
+                (row, matched)
+            })
+        });
+        select_rows(
+            &mut rows,
+            first_row_no,
+            column_count,
+            header_row.as_ref(),
+            match_exps,
+            config,
+            out,
+        )
+    }
+}
+
+// A row and whether each expression matches it.
+type MatchedRow = (CSVRow, Vec<bool>);
+
+// Does the work of svgrep_lines for the rows following the header.
+fn select_rows(
+    rows: &mut dyn Iterator<Item = io::Result<MatchedRow>>,
+    first_row_no: usize,
+    mut column_count: Option<usize>,
+    header: Option<&CSVRow>,
+    match_exps: &[MatchExp],
+    config: &Config,
+    out: &mut dyn Write,
+) -> io::Result<Stats> {
+    let mut profile = if config.profile {
+        Some(Profile::new(header.map_or(vec![], |h| h.cells.clone())))
+    } else {
+        None
+    };
+    let mut summary = if config.stats {
+        Some(Summary::new())
+    } else {
+        None
+    };
+    // The aggregates by the --group-by cells, or else of the single group,
+    // and the number of rows in each group.
+    let new_aggregates =
+        || -> Vec<AggState> { config.aggregates.iter().map(AggState::new).collect() };
+    let mut groups: BTreeMap<Vec<String>, (usize, Vec<AggState>)> = BTreeMap::new();
+    if config.group_by.is_empty() && !config.aggregates.is_empty() {
+        groups.insert(vec![], (0, new_aggregates()));
+    }
+    let mut group_counts: HashMap<String, usize> = HashMap::new();
+    let mut value_counts: HashMap<String, usize> = HashMap::new();
+    let mut last_printed: Option<String> = None;
+    let mut selected: usize = 0;
+    let mut nonmatching: usize = 0;
+    let mut violations: usize = 0;
+    let mut bad_rows: usize = 0;
+    // Context rows are shown with the selection of the first expression.
+    let mut before: VecDeque<(usize, CSVRow)> = VecDeque::with_capacity(config.before_context);
+    let mut after_left: usize = 0;
+    let mut last_row_no: Option<usize> = None;
+    // The selected rows printed at the end with --tail or --sort.
+    let mut buffered: VecDeque<(usize, CSVRow, Vec<bool>)> =
+        VecDeque::with_capacity(config.tail.unwrap_or(0));
+    let selects = |matched: &[bool]| {
+        let hit = if config.match_all {
+            matched.iter().all(|m| *m)
+        } else {
+            matched.iter().any(|m| *m)
+        };
+        hit != config.invert_match
+    };
+
+    // Records are numbered from 1 (or --resume-from-line) without the header,
+    // and the ones before --from-line are already skipped.
+    let mut rows = rows.enumerate();
+    let mut read: usize = 0;
+    let mut sampler = Sampler::new(config);
+    // Like with grep, the after-context of the last match --max-count allows
+    // is still printed, including the matches in it.
+    while config.max_count.is_none_or(|max| selected < max) || after_left > 0 {
+        let (i, (mut row, matched)) = match rows.next() {
+            Some((i, record)) => (i, record?),
+            None => break,
+        };
+        read += 1;
+        if config.progress && read.is_multiple_of(PROGRESS_ROWS) {
+            write_progress(config.file_name.as_deref(), read, selected, false, out)?;
+        }
+        let row_no = first_row_no + i;
+        if config.max_count.is_some_and(|max| selected >= max) {
+            after_left -= 1;
+            make_rectangular(&mut row, &mut column_count, config);
+            write_group_sep(&mut last_row_no, row_no, out)?;
+            match_exps[0].select(&row, Some(row_no), config, out)?;
+            continue;
+        }
+        if config.strict {
+            let expected = *column_count.get_or_insert(row.cells.len());
+            if row.cells.len() != expected {
+                let file = config
+                    .file_name
+                    .as_ref()
+                    .map_or(String::new(), |f| format!("{}: ", f));
+                return Err(input_error(format!(
+                    "{}Row {} has {} columns instead of {}!",
+                    file,
+                    row_no,
+                    row.cells.len(),
+                    expected
+                )));
+            }
+        }
+        // Sampling thins out the matching rows.
+        let hit = selects(&matched) && sampler.keeps();
+        if let Some(ref mut summary) = summary {
+            summary.add(&row, hit, &config.trim);
+        }
+        if !config.require.is_empty() || config.check_columns {
+            if !hit {
+                continue;
+            }
+            selected += 1;
+            violations += check_row(&row, row_no, config, out)?;
+            if config.check_columns {
+                let expected = *column_count.get_or_insert(row.cells.len());
+                if row.cells.len() != expected {
+                    write_file_name(config.file_name.as_deref(), out)?;
+                    writeln!(
+                        out,
+                        "{}: {} columns instead of {}",
+                        row_no,
+                        row.cells.len(),
+                        expected
+                    )?;
+                    bad_rows += 1;
+                }
+            }
+            continue;
+        }
+        if config.list_files {
+            if hit {
+                selected += 1;
+                break;
+            }
+            continue;
+        }
+        if config.count || config.count_nonmatching {
+            // With --tee, the printed rows are counted below.
+            if !hit {
+                nonmatching += 1;
+            } else if !config.tee {
+                selected += 1;
+            }
+            if !config.tee {
+                continue;
+            }
+        }
+        if let Some(ref mut profile) = profile {
+            if hit {
+                profile.add(&row, &config.trim);
+                if !config.tee {
+                    selected += 1;
+                }
+            }
+            if !config.tee {
+                continue;
+            }
+        }
+        if !config.aggregates.is_empty() {
+            if hit {
+                let key = config
+                    .group_by
+                    .iter()
+                    .map(|col| {
+                        String::from(row.cell(*col).map_or("", |c| maybe_trim(c, &config.trim)))
+                    })
+                    .collect();
+                let (rows, aggregates) = groups.entry(key).or_insert_with(|| (0, new_aggregates()));
+                *rows += 1;
+                for agg in aggregates {
+                    agg.add(&row, &config.trim);
+                }
+                if !config.tee {
+                    selected += 1;
+                }
+            }
+            if !config.tee {
+                continue;
+            }
+        }
+        if let Some(col) = config.value_counts {
+            if hit {
+                // Rows missing the column aren't counted.
+                if let Some(cell) = row.cell(col) {
+                    *value_counts
+                        .entry(String::from(maybe_trim(cell, &config.trim)))
+                        .or_insert(0) += 1;
+                }
+                if !config.tee {
+                    selected += 1;
+                }
+            }
+            if !config.tee {
+                continue;
+            }
+        }
+        // Only the printed rows are made rectangular, not the summarized ones.
+        make_rectangular(&mut row, &mut column_count, config);
+        if config.limit_per_group.is_some() || config.on_change.is_some() {
+            if !hit {
+                continue;
+            }
+            let change_key = config
+                .on_change
+                .map(|col| maybe_trim(row.cell(col).unwrap_or(""), &config.trim));
+            if change_key.is_some() && change_key == last_printed.as_deref() {
+                continue;
+            }
+            if let Some((group_col, limit)) = config.limit_per_group {
+                let key = maybe_trim(row.cell(group_col).unwrap_or(""), &config.trim);
+                let count = group_counts.entry(String::from(key)).or_insert(0);
+                if *count >= limit {
+                    continue;
+                }
+                *count += 1;
+            }
+            if let Some(key) = change_key {
+                last_printed = Some(String::from(key));
+            }
+        }
+        if config.before_context > 0 || config.after_context > 0 {
+            if !hit {
+                if after_left > 0 {
+                    after_left -= 1;
+                    write_group_sep(&mut last_row_no, row_no, out)?;
+                    match_exps[0].select(&row, Some(row_no), config, out)?;
+                } else if config.before_context > 0 {
+                    if before.len() == config.before_context {
+                        before.pop_front();
+                    }
+                    before.push_back((row_no, row));
+                }
+                continue;
+            }
+            for (context_no, context_row) in before.drain(..) {
+                write_group_sep(&mut last_row_no, context_no, out)?;
+                match_exps[0].select(&context_row, Some(context_no), config, out)?;
+            }
+            write_group_sep(&mut last_row_no, row_no, out)?;
+            after_left = config.after_context;
+        }
+        if !hit {
+            continue;
+        }
+        selected += 1;
+        if config.tail.is_none() && config.sort.is_none() {
+            print_selected_row(&row, row_no, &matched, match_exps, config, out)?;
+            continue;
+        }
+        if config.tail == Some(buffered.len()) {
+            buffered.pop_front();
+        }
+        buffered.push_back((row_no, row, matched));
+    }
+
+    let mut buffered = Vec::from(buffered);
+    if let Some((col, descending)) = config.sort {
+        // The sort is stable, and missing cells sort as least.
+        buffered.sort_by(|(_, a, _), (_, b, _)| {
+            let order = if config.numeric_sort {
+                match (a.get_number(col), b.get_number(col)) {
+                    (Some(a), Some(b)) => a.total_cmp(&b),
+                    (a, b) => a.is_some().cmp(&b.is_some()),
+                }
+            } else {
+                let trim = |cell| maybe_trim(cell, &config.trim);
+                a.cell(col).map(trim).cmp(&b.cell(col).map(trim))
+            };
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+    }
+    if config.progress {
+        write_progress(config.file_name.as_deref(), read, selected, true, out)?;
+    }
+    for (row_no, row, matched) in buffered {
+        print_selected_row(&row, row_no, &matched, match_exps, config, out)?;
+    }
+    print_table(config, out)?;
+    let file_name = config.file_name.as_deref();
+    if config.check_columns {
+        write_file_name(file_name, out)?;
+        writeln!(
+            out,
+            "{} of {} rows have a wrong column count",
+            bad_rows, selected
+        )?;
+        violations += bad_rows;
+    }
+    // With --tee, standard output remains for the rows.
+    if config.tee {
+        out.flush()?;
+    }
+    let mut err = io::stderr();
+    let agg_out: &mut dyn Write = if config.tee { &mut err } else { out };
+    if config.count {
+        write_file_name(file_name, agg_out)?;
+        writeln!(agg_out, "{}", selected)?;
+    }
+    if config.count_nonmatching {
+        write_file_name(file_name, agg_out)?;
+        writeln!(agg_out, "{}", nonmatching)?;
+    }
+    if let Some(profile) = profile {
+        profile.print(file_name, config.index_base, agg_out)?;
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    if let Some(descending) = config.sort_by_count {
+        // The sort is stable, so groups with the same count stay sorted.
+        groups.sort_by(
+            |(_, (a, _)), (_, (b, _))| {
+                if descending {
+                    b.cmp(a)
+                } else {
+                    a.cmp(b)
+                }
+            },
+        );
+    }
+    for (key, (_, aggregates)) in &groups {
+        let group =
+            Some(key.join(&config.output_separator)).filter(|_| !config.group_by.is_empty());
+        for agg in aggregates {
+            agg.print(
+                file_name,
+                group.as_deref(),
+                config.match_char_cfg.index_base,
+                agg_out,
+            )?;
+        }
+    }
+    if config.value_counts.is_some() {
+        print_value_counts(value_counts, file_name, agg_out)?;
+    }
+    agg_out.flush()?;
+    // Like with --tee, standard output remains for the rows.
+    if let Some(summary) = summary {
+        summary.print(file_name, config.index_base, &mut io::stderr())?;
+    }
+    if config.summary {
+        let mut err = io::stderr();
+        write_file_name(file_name, &mut err)?;
+        writeln!(err, "{} rows matched out of {}", selected, read)?;
+    }
+    Ok(Stats {
+        matched: selected > 0,
+        violations,
+    })
+}
+
+// Yields the records matched by a pool of worker threads with --jobs in
+// input order.  The records are still read and parsed on the calling thread
+// since quoted fields may span lines, and handed out in numbered chunks.  An
+// error reading them is returned after the rows before it.
+struct ParMatches {
+    records: Records,
+    error: Option<io::Error>,
+    chunks: Option<mpsc::SyncSender<(usize, Vec<CSVRow>)>>,
+    results: mpsc::Receiver<(usize, Vec<MatchedRow>)>,
+    sent: usize,
+    next_chunk: usize,
+    finished: HashMap<usize, Vec<MatchedRow>>,
+    current: std::vec::IntoIter<MatchedRow>,
+    max_pending: usize,
+}
+
+impl ParMatches {
+    fn new<'scope, 'env>(
+        scope: &'scope thread::Scope<'scope, 'env>,
+        records: Records,
+        match_exps: &'env [MatchExp],
+        jobs: usize,
+    ) -> ParMatches {
+        let (chunks_tx, chunks_rx) = mpsc::sync_channel::<(usize, Vec<CSVRow>)>(jobs);
+        let (results_tx, results_rx) = mpsc::channel();
+        let chunks_rx = Arc::new(Mutex::new(chunks_rx));
+        for _ in 0..jobs {
+            let chunks_rx = Arc::clone(&chunks_rx);
+            let results_tx = results_tx.clone();
+            scope.spawn(move || loop {
+                // The lock is released before matching the chunk.
+                let next = chunks_rx.lock().unwrap().recv();
+                let (no, rows) = match next {
+                    Ok(chunk) => chunk,
+                    Err(_) => break,
+                };
+                let matched = rows
+                    .into_iter()
+                    .map(|row| {
+                        let matched = match_exps.iter().map(|e| e.matches(&row)).collect();
+                        (row, matched)
+                    })
+                    .collect();
+                if results_tx.send((no, matched)).is_err() {
+                    break;
+                }
+            });
+        }
+        ParMatches {
+            records,
+            error: None,
+            chunks: Some(chunks_tx),
+            results: results_rx,
+            sent: 0,
+            next_chunk: 0,
+            finished: HashMap::new(),
+            current: vec![].into_iter(),
+            max_pending: 2 * jobs,
+        }
+    }
+}
+
+impl Iterator for ParMatches {
+    type Item = io::Result<MatchedRow>;
+
+    fn next(&mut self) -> Option<io::Result<MatchedRow>> {
+        loop {
+            if let Some(row) = self.current.next() {
+                return Some(Ok(row));
+            }
+            while self.chunks.is_some() && self.sent - self.next_chunk < self.max_pending {
+                let mut rows = Vec::with_capacity(PAR_CHUNK_ROWS);
+                for record in self.records.by_ref().take(PAR_CHUNK_ROWS) {
+                    match record {
+                        Ok(row) => rows.push(row),
+                        Err(e) => {
+                            self.error = Some(e);
+                            break;
+                        }
+                    }
+                }
+                // An error ends the input like its end does.
+                let last = rows.len() < PAR_CHUNK_ROWS;
+                if !rows.is_empty() {
+                    let chunks = self.chunks.as_ref().unwrap();
+                    chunks.send((self.sent, rows)).ok()?;
+                    self.sent += 1;
+                }
+                if last {
+                    // Lets the workers finish.
+                    self.chunks = None;
+                }
+            }
+            if self.next_chunk == self.sent {
+                return self.error.take().map(Err);
+            }
+            while !self.finished.contains_key(&self.next_chunk) {
+                let (no, rows) = self.results.recv().ok()?;
+                self.finished.insert(no, rows);
+            }
+            self.current = self.finished.remove(&self.next_chunk).unwrap().into_iter();
+            self.next_chunk += 1;
+        }
+    }
+}
+
+// Prints one line for each cell of row violating reqs and returns their
+// number.
+fn check_row(
+    row: &CSVRow,
+    line_no: usize,
+    config: &Config,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let mut violations = 0;
+    for req in &config.require {
+        let cell = row.cell(req.col);
+        if cell.is_some_and(|c| req.kind.accepts(c)) {
+            continue;
+        }
+        let col = to_base(req.col, config.match_char_cfg.index_base);
+        write_file_name(config.file_name.as_deref(), out)?;
+        match cell {
+            None => writeln!(out, "{}: column {} is missing", line_no, col)?,
+            Some(cell) => writeln!(
+                out,
+                "{}: column {} is no {}: {}",
+                line_no,
+                col,
+                req.kind.name(),
+                cell
+            )?,
+        }
+        violations += 1;
+    }
+    Ok(violations)
+}
+
+// Picks the candidate separator splitting the lines in the first
+// sniff_bytes of the input (at least one) into the same number of cells,
+// preferring more cells and then earlier candidates, and makes it the
+// config's separator.  The output separator follows unless it differs from
+// the separator.  Returns the lines including the sampled ones, and the
+// separator if one splits any line.
+pub fn detect_separator(
+    mut lines: InputLines,
+    sniff_bytes: usize,
+    config: &mut Config,
+) -> (InputLines, Option<&'static str>) {
+    let mut sample: Vec<io::Result<(usize, Line)>> = vec![];
+    let mut sampled = 0;
+    while sample.is_empty() || sampled < sniff_bytes {
+        match lines.next() {
+            Some(Ok((offset, line))) => {
+                // Counting the terminator, too.
+                sampled += line.bytes.len() + 1;
+                sample.push(Ok((offset, line)));
+            }
+            Some(Err(e)) => {
+                sample.push(Err(e));
+                break;
+            }
+            None => break,
+        }
+    }
+    let texts: Vec<String> = sample
+        .iter()
+        .filter_map(|line| line.as_ref().ok())
+        .filter_map(|(_, line)| line.encoding.decode(line.bytes.clone()).ok())
+        .filter(|text| !text.is_empty())
+        .collect();
+    let mut best: Option<(&'static str, bool, usize)> = None;
+    for &candidate in SEPARATOR_CANDIDATES {
+        let sep = Separator::Literal(String::from(candidate));
+        let counts: Vec<Option<usize>> = texts
+            .iter()
+            .map(|line| {
+                CSVRow::parse_line(line, &sep, config.quoting, config.collapse_separators)
+                    .map(|r| r.cells.len())
+            })
+            .collect();
+        let min = counts.iter().map(|c| c.unwrap_or(0)).min().unwrap_or(0);
+        let consistent = counts.iter().all(|c| c.is_some() && *c == counts[0]);
+        if min > 1 && best.is_none_or(|(_, c, m)| (consistent, min) > (c, m)) {
+            best = Some((candidate, consistent, min));
+        }
+    }
+    let detected = best.map(|(sep, _, _)| sep);
+    if let Some(sep) = detected {
+        if config.output_separator == config.separator.as_str() {
+            config.output_separator = String::from(sep);
+        }
+        config.separator = Separator::Literal(String::from(sep));
+    }
+    (Box::new(sample.into_iter().chain(lines)), detected)
+}
+
+// Prints the separator, the column counts of the first max_rows records,
+// and with --header the names in the first one.
+pub fn inspect_input(
+    lines: InputLines,
+    max_rows: usize,
+    config: &Config,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let rows = Records::new(lines, config)
+        .take(max_rows)
+        .collect::<io::Result<Vec<CSVRow>>>()?;
+    let file_name = config.file_name.as_deref();
+    write_file_name(file_name, out)?;
+    writeln!(out, "separator: {:?}", config.separator.as_str())?;
+    let min = rows.iter().map(|r| r.cells.len()).min().unwrap_or(0);
+    let max = rows.iter().map(|r| r.cells.len()).max().unwrap_or(0);
+    write_file_name(file_name, out)?;
+    if min == max {
+        writeln!(out, "columns: {}", min)?;
+    } else {
+        writeln!(out, "columns: {} to {}", min, max)?;
+    }
+    if let Some(header) = rows.first().filter(|_| config.header) {
+        write_file_name(file_name, out)?;
+        writeln!(out, "header: {}", header.cells.join(", "))?;
+    }
+    write_file_name(file_name, out)?;
+    writeln!(out, "rows read: {}", rows.len())?;
+    out.flush()
+}
+
+// Samples the first rows of the input and prints what svgrep makes of them
+// as a single JSON object.
+pub fn describe_input(lines: InputLines, config: &Config, out: &mut dyn Write) -> io::Result<()> {
+    let mut rows = 0;
+    let mut col_counts: HashMap<usize, usize> = HashMap::new();
+    let mut records = Records::new(lines, config);
+    // With --header, the first row names the columns and is no sampled row.
+    let header = if config.header {
+        records.next().transpose()?
+    } else {
+        None
+    };
+    for row in records.by_ref().take(DESCRIBE_SAMPLE_ROWS) {
+        let row = row?;
+        rows += 1;
+        *col_counts.entry(row.cells.len()).or_insert(0) += 1;
+    }
+    // The most frequent cell count wins, ties go to the larger count.
+    let columns = col_counts
+        .iter()
+        .max_by_key(|(cols, n)| (**n, **cols))
+        .map_or(0, |(cols, _)| *cols);
+    writeln!(
+        out,
+        "{{\"separator\":{},\"columns\":{},\"header\":{},\"quoted\":{},\"rows_sampled\":{}}}",
+        json_string(config.separator.as_str()),
+        columns,
+        header.map_or(String::from("null"), |header| {
+            let names: Vec<String> = header.cells.iter().map(|c| json_string(c.trim())).collect();
+            format!("[{}]", names.join(","))
+        }),
+        records.quoted,
+        rows
+    )?;
+    out.flush()
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// The messages of errors in the input or the expressions built from its
+// header, as opposed to errors writing the output.
+#[derive(Debug)]
+pub struct InputError(pub Vec<String>);
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.join("\n"))
+    }
+}
+
+impl error::Error for InputError {}
+
+fn input_errors(msgs: Vec<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, InputError(msgs))
+}
+
+fn input_error(msg: String) -> io::Error {
+    input_errors(vec![msg])
+}
+
+// Splits s at each occurrence of sep which is not escaped with a backslash
+// into at most limit parts (0 means no limit).  Escapes are kept so that
+// nested splits still see them; see unescape_ops.  An occurrence of sep
+// directly after one of not_after is no split point either.
+fn split_unescaped<'a>(s: &'a str, sep: &str, limit: usize, not_after: &[String]) -> Vec<&'a str> {
+    let (bytes, sep) = (s.as_bytes(), sep.as_bytes());
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() && !sep.is_empty() && (limit == 0 || parts.len() + 1 < limit) {
+        if bytes[i] == b'\\' {
+            i += 2;
+        } else if bytes[i..].starts_with(sep)
+            && !not_after.iter().any(|p| bytes[..i].ends_with(p.as_bytes()))
+        {
+            parts.push(&s[start..i]);
+            i += sep.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+// Replaces escaped operator strings like \& with the plain operator.  An
+// escaped backslash \\ is kept as is, so it remains a regex escape.
+fn unescape_ops(s: &str, match_char_cfg: &MatchCharCfg) -> String {
+    let ops = [
+        &match_char_cfg.cell_select_char,
+        &match_char_cfg.match_conj_char,
+        &match_char_cfg.match_disj_char,
+        &match_char_cfg.matches_char,
+    ];
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('\\') {
+        result.push_str(&rest[..pos]);
+        let escaped = &rest[pos + 1..];
+        match ops
+            .iter()
+            .find(|op| !op.is_empty() && escaped.starts_with(op.as_str()))
+        {
+            Some(op) => {
+                result.push_str(op);
+                rest = &escaped[op.len()..];
+            }
+            None if escaped.starts_with('\\') => {
+                result.push_str("\\\\");
+                rest = &escaped[1..];
+            }
+            None => {
+                result.push('\\');
+                rest = escaped;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Resolves a column given by its index counted from base, negative ones
+// counting from the end, or, with --header, by its name.
+fn col_index(spec: &str, base: usize, names: Option<&ColNames>) -> Result<isize, String> {
+    if let Ok(idx) = spec.parse::<isize>() {
+        return from_base(idx, base);
+    }
+    match names.map(|n| n.get(spec.trim())) {
+        Some(Some(idx)) => Ok(*idx as isize),
+        Some(None) => Err(format!("There is no column named '{}'!", spec)),
+        None => Err(format!("'{}' is no valid column spec!", spec)),
+    }
+}
+
+// Converts the column idx counted from base to the 0-based one.
+fn from_base(idx: isize, base: usize) -> Result<isize, String> {
+    match idx {
+        _ if idx < 0 => Ok(idx),
+        _ if idx < base as isize => Err(format!(
+            "There is no column {}, the first one is {}!",
+            idx, base
+        )),
+        _ => Ok(idx - base as isize),
+    }
+}
+
+// With anchored, rx must match the whole cell like with --line-regexp.
+fn build_rx(rx: &str, regex_cfg: &RegexCfg, anchored: bool) -> Result<Regex, String> {
+    let rx = if regex_cfg.fixed_strings {
+        regex::escape(rx)
+    } else {
+        String::from(rx)
+    };
+    // The group keeps alternations like a|b within the boundaries.
+    let rx = if regex_cfg.word_regexp {
+        format!(r"\b(?:{})\b", rx)
+    } else {
+        rx
+    };
+    let anchored = anchored || regex_cfg.line_regexp;
+    let rx = match regex_cfg.trim {
+        Some(ref trim) if anchored => {
+            let class = trim.chars.as_ref().map_or(String::from(r"\s"), |cs| {
+                format!("[{}]", regex::escape(&cs.iter().collect::<String>()))
+            });
+            let (left, right) = match trim.side {
+                TrimSide::Left => (format!("{}*", class), String::new()),
+                TrimSide::Right => (String::new(), format!("{}*", class)),
+                TrimSide::Both => (format!("{}*", class), format!("{}*", class)),
+            };
+            format!("^{}(?:{}){}$", left, rx, right)
+        }
+        _ if anchored => format!("^(?:{})$", rx),
+        _ => rx,
+    };
+    let mut builder = RegexBuilder::new(&rx);
+    builder.case_insensitive(regex_cfg.ignore_case);
+    if let Some(limit) = regex_cfg.size_limit {
+        builder.size_limit(limit);
+    }
+    if let Some(limit) = regex_cfg.dfa_size_limit {
+        builder.dfa_size_limit(limit);
+    }
+    builder.build().map_err(|e| match e {
+        regex::Error::CompiledTooBig(limit) => format!(
+            "Regex '{}' exceeds the --regex-size-limit of {} bytes!",
+            rx, limit
+        ),
+        _ => format!("Invalid regex '{}': {}", rx, e),
+    })
+}
+
+// Adds the clauses of m to exp.  The disjunction char binds tighter than the
+// conjunction char, so 1=a|1=b&2=c means (1=a or 1=b) and 2=c.
+fn build_rxs(
+    m: Option<&str>,
+    match_char_cfg: &MatchCharCfg,
+    regex_cfg: &RegexCfg,
+    names: Option<&ColNames>,
+    exp: &mut MatchExp,
+) -> Result<(), String> {
+    if let Some(m) = m {
+        let same_as_rx = Regex::new(
+            format!(
+                r"^(.+?)(!|{0}){0}{1}(.+)$",
+                regex::escape(&match_char_cfg.matches_char),
+                regex::escape(&match_char_cfg.cell_select_char)
+            )
+            .as_str(),
+        )
+        .expect("Invalid Regex in the code!");
+        // The clauses are slices of m, so their offsets give the positions.
+        let mut clause_no = 0;
+        for conjunct in split_unescaped(m, &match_char_cfg.match_conj_char, 0, &[]) {
+            let mut alternatives = vec![];
+            for clause in split_alternatives(conjunct, match_char_cfg, names) {
+                clause_no += 1;
+                let pos = m[..clause.as_ptr() as usize - m.as_ptr() as usize]
+                    .chars()
+                    .count()
+                    + 1;
+                let at = ClausePos {
+                    exp: m,
+                    no: clause_no,
+                    pos,
+                };
+                alternatives.push(build_clause(
+                    clause,
+                    &at,
+                    &same_as_rx,
+                    match_char_cfg,
+                    regex_cfg,
+                    names,
+                )?);
+            }
+            exp.clauses.push(alternatives);
+        }
+    }
+    Ok(())
+}
+
+// Splits conjunct at the disjunction chars followed by a clause, so that
+// regex alternations like 0=a|b or 0=^(3|8)$ stay in their clause.
+fn split_alternatives<'a>(
+    conjunct: &'a str,
+    match_char_cfg: &MatchCharCfg,
+    names: Option<&ColNames>,
+) -> Vec<&'a str> {
+    let mut alternatives: Vec<&'a str> = vec![];
+    let parts = split_unescaped(conjunct, &match_char_cfg.match_disj_char, 0, &[]);
+    for part in parts {
+        match alternatives.last_mut() {
+            Some(last) if !starts_clause(part, match_char_cfg, names) => {
+                // Extend the previous clause up to the end of part.
+                let start = last.as_ptr() as usize - conjunct.as_ptr() as usize;
+                let end = part.as_ptr() as usize - conjunct.as_ptr() as usize + part.len();
+                *last = &conjunct[start..end];
+            }
+            _ => alternatives.push(part),
+        }
+    }
+    alternatives
+}
+
+// Returns whether part begins with a column and the matches char or a
+// predicate, i.e., <col>=, <col>!=, or <col>:<pred>.
+fn starts_clause(part: &str, match_char_cfg: &MatchCharCfg, names: Option<&ColNames>) -> bool {
+    let col = match part.find(match_char_cfg.matches_char.as_str()) {
+        Some(end) => &part[..end],
+        None => match PRED_RX.captures(part) {
+            Some(caps) => caps.get(1).map_or("", |c| c.as_str()),
+            None => return false,
+        },
+    };
+    let col = col.strip_suffix('!').unwrap_or(col).trim();
+    ASTERISK_RX.is_match(col)
+        || col == LINE_COL
+        || col.parse::<isize>().is_ok()
+        || names.is_some_and(|names| names.contains_key(col))
+}
+
+// Where a clause occurs in the clauses of a --match expression, used to
+// point at malformed clauses.
+struct ClausePos<'a> {
+    exp: &'a str,
+    no: usize,
+    pos: usize,
+}
+
+impl ClausePos<'_> {
+    fn error(&self, clause: &str, problem: &str) -> String {
+        let clause = if clause.is_empty() {
+            String::from("Empty clause")
+        } else {
+            format!("Clause '{}'", clause)
+        };
+        format!(
+            "{} (clause {} at position {} of '{}') {}!",
+            clause, self.no, self.pos, self.exp, problem
+        )
+    }
+}
+
+fn build_clause(
+    clause: &str,
+    at: &ClausePos,
+    same_as_rx: &Regex,
+    match_char_cfg: &MatchCharCfg,
+    regex_cfg: &RegexCfg,
+    names: Option<&ColNames>,
+) -> Result<Clause, String> {
+    if clause.is_empty() {
+        return Err(at.error(
+            clause,
+            format!(
+                "is not allowed, e.g., from a doubled, leading, or trailing {} or {}",
+                match_char_cfg.match_conj_char, match_char_cfg.match_disj_char
+            )
+            .as_str(),
+        ));
+    }
+    // A column part containing the matches char means the : or ==@ belongs
+    // to the regex of an ordinary clause.
+    let is_col = |c: &str| !c.contains(match_char_cfg.matches_char.as_str());
+    if let Some(caps) = PRED_RX.captures(clause).filter(|c| is_col(&c[1])) {
+        let cell_idx = col_index(&caps[1], match_char_cfg.index_base, names)?;
+        let args = caps.get(3).map_or("", |a| a.as_str());
+        return Ok(Clause::Pred(
+            cell_idx,
+            build_pred(&caps[2], args, match_char_cfg.index_base, names)?,
+            regex_cfg.trim.clone(),
+        ));
+    }
+    if let Some(caps) = same_as_rx
+        .captures(clause)
+        .filter(|c| is_col(&c[1]) && !c[1].ends_with('!'))
+    {
+        let cell_idx = col_index(&caps[1], match_char_cfg.index_base, names)?;
+        let other_idx = col_index(&caps[3], match_char_cfg.index_base, names)?;
+        return Ok(Clause::Pred(
+            cell_idx,
+            CellPred::SameAs(other_idx, &caps[2] == "!"),
+            regex_cfg.trim.clone(),
+        ));
+    }
+    let col_and_rx = split_unescaped(clause, &match_char_cfg.matches_char, 2, &[]);
+    if col_and_rx.len() < 2 {
+        return Err(at.error(
+            clause,
+            format!(
+                "is missing the {} between column and regex",
+                match_char_cfg.matches_char
+            )
+            .as_str(),
+        ));
+    }
+    if col_and_rx[1].is_empty() {
+        return Err(at.error(clause, "is missing a regex"));
+    }
+    // With <col>!=<regex>, the clause holds if the cell doesn't match,
+    // including when it is missing.
+    let (col, negated) = match col_and_rx[0].strip_suffix('!') {
+        Some(col) => (col, true),
+        None => (col_and_rx[0], false),
+    };
+    if col.trim().is_empty() {
+        return Err(at.error(clause, "is missing a column"));
+    }
+    let rx = unescape_ops(col_and_rx[1], match_char_cfg);
+    let positive = if ASTERISK_RX.is_match(col) {
+        Clause::AnyCell(
+            build_rx(&rx, regex_cfg, false)?,
+            match_char_cfg.scan_columns.unwrap_or(usize::MAX),
+        )
+    } else if col.trim() == LINE_COL {
+        Clause::Line(build_rx(&rx, regex_cfg, false)?)
+    } else {
+        let cell_idx = col_index(col, match_char_cfg.index_base, names)?;
+        match build_set_pred(&rx) {
+            Some(pred) => Clause::Pred(cell_idx, pred, regex_cfg.trim.clone()),
+            None => Clause::Cell(
+                cell_idx,
+                build_rx(&rx, regex_cfg, regex_cfg.anchor_columns)?,
+            ),
+        }
+    };
+    if negated {
+        Ok(Clause::Not(Box::new(positive)))
+    } else {
+        Ok(positive)
+    }
+}
+
+fn build_set_pred(rx: &str) -> Option<CellPred> {
+    let (negated, values) = if let Some(values) = rx.strip_prefix(IN_PREFIX) {
+        (false, values)
+    } else if let Some(values) = rx.strip_prefix(NOT_IN_PREFIX) {
+        (true, values)
+    } else {
+        return None;
+    };
+    let set = values.split(',').map(|v| String::from(v.trim())).collect();
+    Some(CellPred::InSet(set, negated))
+}
+
+fn build_pred(
+    name: &str,
+    args: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<CellPred, String> {
+    if name != "between" && !args.is_empty() {
+        return Err(format!("Predicate {} takes no arguments!", name));
+    }
+    match name {
+        "between" => {
+            let arg_idxs = args
+                .split(',')
+                .map(|a| col_index(a, base, names))
+                .collect::<Result<Vec<isize>, String>>()?;
+            if arg_idxs.len() != 2 {
+                return Err(String::from(
+                    "between needs exactly two columns, e.g. 1:between(2,3)!",
+                ));
+            }
+            Ok(CellPred::Between(arg_idxs[0], arg_idxs[1]))
+        }
+        "is-number" => Ok(CellPred::IsNumber),
+        "is-date" => Ok(CellPred::IsDate),
+        "is-email" => Ok(CellPred::IsEmail),
+        "is-empty" => Ok(CellPred::IsEmpty(false)),
+        "is-nonempty" => Ok(CellPred::IsEmpty(true)),
+        _ => Err(format!("'{}' is no valid predicate!", name)),
+    }
+}
+
+// Returns the selection and the (col, group) pairs of entries <col>:<group>.
+fn build_cell_select(
+    s: Option<&str>,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<(CellSelect, Vec<(isize, usize)>), String> {
+    match s {
+        None => Ok((CellSelect::ALL, vec![])),
+        Some("!") => Ok((CellSelect::Unmatched, vec![])),
+        Some(v) => {
+            let (except, v) = match v.strip_prefix('^') {
+                Some(rest) => (true, rest),
+                None => (false, v),
+            };
+            let mut cols = vec![];
+            let mut groups = vec![];
+            let mut shown = HashMap::new();
+            for entry in v.split(',') {
+                // Column names may contain colons, groups are numbers.
+                let (range, group) = match entry.rsplit_once(':') {
+                    Some((range, group)) if !except => match group.parse::<usize>() {
+                        Ok(group) => (range, Some(group)),
+                        Err(_) => (entry, None),
+                    },
+                    _ => (entry, None),
+                };
+                for col in col_range(range, base, names)? {
+                    // The group replaces the cell wherever it is displayed.
+                    if shown.insert(col, group).is_some_and(|g| g != group) {
+                        return Err(format!(
+                            "Column {} is displayed with different groups!",
+                            to_base(col, base)
+                        ));
+                    }
+                    if let Some(group) = group {
+                        groups.push((col, group));
+                    }
+                    cols.push(col);
+                }
+            }
+            if except {
+                Ok((CellSelect::Except(cols), groups))
+            } else {
+                Ok((CellSelect::Some(cols), groups))
+            }
+        }
+    }
+}
+
+// Expands a selection token N-M into the columns N to M inclusive, in
+// descending order if M < N.  Any other token, e.g., -1, is a single column.
+fn col_range(spec: &str, base: usize, names: Option<&ColNames>) -> Result<Vec<isize>, String> {
+    // Negative columns can't be range bounds since they depend on the row.
+    let bounds: Vec<Option<isize>> = spec
+        .splitn(2, '-')
+        .map(|b| b.parse().ok().filter(|b| *b >= 0))
+        .collect();
+    match bounds[..] {
+        [Some(from), Some(to)] => {
+            let (from, to) = (from_base(from, base)?, from_base(to, base)?);
+            if from <= to {
+                Ok((from..=to).collect())
+            } else {
+                Ok((to..=from).rev().collect())
+            }
+        }
+        _ => Ok(vec![col_index(spec, base, names)?]),
+    }
+}
+
+pub fn build_match_exp(
+    match_val: &str,
+    match_char_cfg: &MatchCharCfg,
+    regex_cfg: &RegexCfg,
+    names: Option<&ColNames>,
+) -> Result<MatchExp, String> {
+    // The @ in cross-column clauses like 2==@3 doesn't start the selection.
+    let m = &match_char_cfg.matches_char;
+    let parts = split_unescaped(
+        match_val,
+        &match_char_cfg.cell_select_char,
+        0,
+        &[format!("{}{}", m, m), format!("!{}", m)],
+    );
+    let sel = &match_char_cfg.cell_select_char;
+    if parts.len() > 2 {
+        return Err(format!(
+            "--match expression '{}' has more than one {}!",
+            match_val, sel
+        ));
+    }
+    if parts.len() == 2 && parts[1].is_empty() {
+        return Err(format!(
+            "--match expression '{}' has no columns after {}!",
+            match_val, sel
+        ));
+    }
+
+    let mut exp = MatchExp::new();
+    let clauses = match parts[0].strip_prefix('!') {
+        Some(clauses) => {
+            exp.negated = true;
+            clauses
+        }
+        None => parts[0],
+    };
+    build_rxs(
+        Some(clauses).filter(|clauses| !clauses.is_empty()),
+        match_char_cfg,
+        regex_cfg,
+        names,
+        &mut exp,
+    )?;
+    let (sel, groups) = build_cell_select(parts.get(1).cloned(), match_char_cfg.index_base, names)?;
+    exp.sel = sel;
+    exp.groups = groups;
+    Ok(exp)
+}
+
+pub fn build_match_exps(
+    match_vals: &[String],
+    match_char_cfg: &MatchCharCfg,
+    regex_cfg: &RegexCfg,
+    names: Option<&ColNames>,
+) -> Result<Vec<MatchExp>, Vec<String>> {
+    // All expressions are built so that each bad one is reported.
+    let mut exps = vec![];
+    let mut msgs = vec![];
+    for match_val in match_vals {
+        match build_match_exp(match_val, match_char_cfg, regex_cfg, names) {
+            Ok(exp) => exps.push(exp),
+            Err(msg) => msgs.push(msg),
+        }
+    }
+    if msgs.is_empty() {
+        Ok(exps)
+    } else {
+        Err(msgs)
+    }
+}
+
+// Splits an --template into its text and its {<col>} references, where {{
+// and }} stand for literal braces.
+pub fn build_template(
+    spec: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<Vec<TemplatePart>, String> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = spec.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut col = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if c != '{' => col.push(c),
+                        _ => return Err(format!("--template '{}' has an unclosed {{!", spec)),
+                    }
+                }
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(text.split_off(0)));
+                }
+                parts.push(TemplatePart::Col(col_index(col.trim(), base, names)?));
+            }
+            '}' => return Err(format!("--template '{}' has an unescaped }}!", spec)),
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(TemplatePart::Text(text));
+    }
+    Ok(parts)
+}
+
+// Reads the --header of lines and returns the columns of its names, which
+// are none if lines are empty.
+pub fn read_col_names(lines: InputLines, config: &Config) -> io::Result<ColNames> {
+    let header = Records::new(lines, config).next().transpose()?;
+    match header {
+        Some(header) => col_names(&header, config.duplicate_names).map_err(input_error),
+        None => Ok(ColNames::new()),
+    }
+}
+
+// Returns the columns of the names in header, resolving the ones shared by
+// several columns according to duplicates.
+fn col_names(header: &CSVRow, duplicates: DuplicateNames) -> Result<ColNames, String> {
+    let mut names = ColNames::new();
+    let mut later = vec![];
+    for (i, name) in header.cells.iter().enumerate() {
+        let mut first = match names.entry(String::from(name.trim())) {
+            Entry::Vacant(entry) => {
+                entry.insert(i);
+                continue;
+            }
+            Entry::Occupied(entry) => entry,
+        };
+        match duplicates {
+            DuplicateNames::First => (),
+            DuplicateNames::Last => {
+                first.insert(i);
+            }
+            // Numbered after all names are known so that no real one is taken.
+            DuplicateNames::Number => later.push((first.key().clone(), i)),
+            DuplicateNames::Reject => {
+                return Err(format!(
+                    "The header name '{}' is used by columns {} and {}!",
+                    first.key(),
+                    first.get(),
+                    i
+                ))
+            }
+        }
+    }
+    for (name, i) in later {
+        let numbered = (2..)
+            .map(|n| format!("{}_{}", name, n))
+            .find(|numbered| !names.contains_key(numbered))
+            .unwrap();
+        names.insert(numbered, i);
+    }
+    Ok(names)
+}
+
+// Returns what built is, or None after noting why it failed in msgs.
+fn noted<T>(built: Result<T, String>, msgs: &mut Vec<String>) -> Option<T> {
+    built.map_err(|msg| msgs.push(msg)).ok()
+}
+
+impl Config {
+    // Builds the options taking columns from the column_specs, counted from
+    // the --match base and with the --header names if given.  Each bad spec
+    // is reported.
+    pub fn build_columns(&mut self, names: Option<&ColNames>) -> Result<(), Vec<String>> {
+        let base = self.match_char_cfg.index_base;
+        let specs = &self.column_specs;
+        let mut msgs = vec![];
+        let aggregates = specs
+            .aggregates
+            .iter()
+            .filter_map(|s| noted(build_aggregate(s, base, names), &mut msgs))
+            .collect();
+        let group_by = specs
+            .group_by
+            .as_ref()
+            .and_then(|s| noted(build_group_by(s, base, names), &mut msgs));
+        let value_counts = specs
+            .value_counts
+            .as_ref()
+            .and_then(|s| noted(build_column(s, "value-counts", base, names), &mut msgs));
+        let require = specs
+            .require
+            .as_ref()
+            .and_then(|s| noted(build_column_reqs(s, base, names), &mut msgs));
+        let replacements = specs
+            .replacements
+            .iter()
+            .filter_map(|s| noted(build_replacement(s, base, names), &mut msgs))
+            .collect();
+        let limit_per_group = specs
+            .limit_per_group
+            .as_ref()
+            .and_then(|s| noted(build_limit_per_group(s, base, names), &mut msgs));
+        let on_change = specs
+            .on_change
+            .as_ref()
+            .and_then(|s| noted(build_column(s, "on-change", base, names), &mut msgs));
+        let sort = specs
+            .sort
+            .as_ref()
+            .and_then(|s| noted(build_sort(s, base, names), &mut msgs));
+        let join_col = specs
+            .join_col
+            .as_ref()
+            .and_then(|s| noted(build_column(s, "on", base, names), &mut msgs));
+        if !msgs.is_empty() {
+            return Err(msgs);
+        }
+        self.aggregates = aggregates;
+        self.group_by = group_by.unwrap_or_default();
+        self.value_counts = value_counts;
+        self.require = require.unwrap_or_default();
+        self.replacements = replacements;
+        self.limit_per_group = limit_per_group;
+        self.on_change = on_change;
+        self.sort = sort;
+        self.join_col = join_col.unwrap_or(0);
+        Ok(())
+    }
+}
+
+// Resolves the column col of the option opt like the columns of --match
+// expressions, counted from base and possibly named.
+pub fn build_column(
+    col: &str,
+    opt: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<isize, String> {
+    col_index(col.trim(), base, names)
+        .map_err(|msg| format!("{} in --{}!", msg.trim_end_matches('!'), opt))
+}
+
+pub fn build_aggregate(
+    spec: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<Aggregate, String> {
+    let (kind, col) = spec
+        .split_once(':')
+        .and_then(|(kind, col)| AggKind::from_name(kind.trim()).map(|kind| (kind, col)))
+        .ok_or_else(|| format!("'{}' is no valid --agg <kind>:<col>!", spec))?;
+    Ok(Aggregate {
+        kind,
+        col: build_column(col, "agg", base, names)?,
+    })
+}
+
+pub fn build_group_by(
+    spec: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<Vec<isize>, String> {
+    spec.split(',')
+        .map(|col| build_column(col, "group-by", base, names))
+        .collect()
+}
+
+pub fn build_renames(spec: &str) -> Result<Vec<(String, String)>, String> {
+    spec.split(',')
+        .map(|entry| match entry.split_once('=') {
+            Some((col, name)) if !col.trim().is_empty() => {
+                Ok((String::from(col.trim()), String::from(name)))
+            }
+            _ => Err(format!("'{}' is no valid --rename <col>=<name>!", entry)),
+        })
+        .collect()
+}
+
+impl Join {
+    // Returns row followed by the cells of each joined row with its key, or
+    // with the empty cells of a left join if there's none.
+    fn rows(&self, row: CSVRow, col: isize) -> Vec<CSVRow> {
+        let joined = row.cell(col).and_then(|key| self.rows.get(key.trim()));
+        match joined {
+            Some(others) => others
+                .iter()
+                .map(|cells| {
+                    let mut r = row.clone();
+                    r.cells.extend(cells.iter().cloned());
+                    r
+                })
+                .collect(),
+            None if self.left => {
+                let mut r = row;
+                r.cells.resize(r.cells.len() + self.width, String::new());
+                vec![r]
+            }
+            None => vec![],
+        }
+    }
+}
+
+// Reads the rows to --join by their cells in other_col, trimmed, which is
+// the first one if None.  With --header, the first row gives the joined
+// names, which other_col may use.  With all, rows with the same key are all
+// joined, otherwise only the first one.
+pub fn read_join(
+    file_name: &str,
+    buffer_size: usize,
+    other_col: Option<&str>,
+    left: bool,
+    all: bool,
+    config: &Config,
+) -> io::Result<Join> {
+    let lines = line_iter(
+        Some(file_name),
+        buffer_size,
+        true,
+        config.null_data,
+        config.keep_bom,
+        config.encoding,
+        false,
+    )?;
+    let mut join = Join {
+        rows: HashMap::new(),
+        names: vec![],
+        width: 0,
+        left,
+    };
+    let mut records = Records::new(lines, config);
+    let first = match records.next().transpose()? {
+        Some(first) => first,
+        None => return Ok(join),
+    };
+    let names = if config.header {
+        Some(col_names(&first, config.duplicate_names).map_err(input_error)?)
+    } else {
+        None
+    };
+    let other_col = match other_col {
+        Some(col) => build_column(col, "on", config.match_char_cfg.index_base, names.as_ref())
+            .map_err(input_error)?,
+        None => 0,
+    };
+    for (i, row) in iter::once(Ok(first)).chain(records).enumerate() {
+        let mut row = row?;
+        let key = match row.index(other_col) {
+            Some(idx) => row.cells.remove(idx),
+            None => continue,
+        };
+        if i == 0 {
+            join.width = row.cells.len();
+            if config.header {
+                join.names = row.cells;
+                continue;
+            }
+        }
+        let others = join.rows.entry(String::from(key.trim())).or_default();
+        if all || others.is_empty() {
+            others.push(row.cells);
+        }
+    }
+    Ok(join)
+}
+
+// Splits an --on spec into the column of the input and the one of the
+// --join file, which are resolved with their own --header names.
+pub fn build_join_on(spec: &str) -> Result<(&str, &str), String> {
+    match spec.split_once('=') {
+        Some((col, other)) if !col.trim().is_empty() && !other.trim().is_empty() => {
+            Ok((col, other))
+        }
+        _ => Err(format!("'{}' is no valid --on <col>=<col>!", spec)),
+    }
+}
+
+pub fn build_sort(
+    spec: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<(isize, bool), String> {
+    // Column names may contain colons, so anything but a trailing order
+    // belongs to the column.
+    let (col, descending) = match spec.rsplit_once(':') {
+        Some((col, "asc")) => (col, false),
+        Some((col, "desc")) => (col, true),
+        _ => (spec, false),
+    };
+    Ok((build_column(col, "sort", base, names)?, descending))
+}
+
+pub fn build_limit_per_group(
+    spec: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<(isize, usize), String> {
+    match spec
+        .rsplit_once(':')
+        .map(|(col, n)| (col, n.parse::<usize>()))
+    {
+        Some((col, Ok(n))) => Ok((build_column(col, "limit-per-group", base, names)?, n)),
+        _ => Err(format!(
+            "'{}' is no valid --limit-per-group <col>:<n>!",
+            spec
+        )),
+    }
+}
+
+pub fn build_column_reqs(
+    spec: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<Vec<ColumnReq>, String> {
+    spec.split(',')
+        .map(|col_spec| {
+            let col_and_type: Vec<&str> = col_spec.splitn(2, ':').collect();
+            let col = build_column(col_and_type[0], "require", base, names)?;
+            let kind = match col_and_type.get(1) {
+                None => ColType::Str,
+                Some(name) => ColType::from_name(name.trim())
+                    .ok_or_else(|| format!("'{}' is no valid type in --require!", name))?,
+            };
+            Ok(ColumnReq { col, kind })
+        })
+        .collect()
+}
+
+// Parses a --replace spec <col>/<regex>/<replacement> where \/ stands for a
+// literal slash.
+pub fn build_replacement(
+    spec: &str,
+    base: usize,
+    names: Option<&ColNames>,
+) -> Result<Replacement, String> {
+    let parts: Vec<String> = split_unescaped(spec, "/", 3, &[])
+        .into_iter()
+        .map(|p| p.replace("\\/", "/"))
+        .collect();
+    if parts.len() != 3 {
+        return Err(format!("'{}' is no valid --replace spec!", spec));
+    }
+    let col = build_column(&parts[0], "replace", base, names)?;
+    let rx = Regex::new(&parts[1])
+        .map_err(|e| format!("Invalid regex '{}' in --replace: {}", parts[1], e))?;
+    Ok(Replacement {
+        col,
+        rx,
+        replacement: parts[2].clone(),
+    })
+}
+
+const TABLE_GAP: &str = "  ";
+const MATCH_COLOR: &str = "\x1b[01;31m\x1b[K";
+const COLOR_END: &str = "\x1b[m\x1b[K";
+// Erases the rest of the terminal line, e.g., of a longer progress line.
+const CLEAR_LINE: &str = "\x1b[K";
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+// The chars of the bytes 0x80 to 0x9f in Windows-1252, which are control
+// chars in Latin-1.  The five unassigned bytes keep their Latin-1 meaning.
+const WINDOWS_1252_C1: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+const DESCRIBE_SAMPLE_ROWS: usize = 1000;
+const PAR_CHUNK_ROWS: usize = 1024;
+const PROGRESS_ROWS: usize = 100_000;
+const SEPARATOR_CANDIDATES: &[&str] = &[",", ";", "\t", "|"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Greps input with the --match expressions exps into a buffer and
+    // returns the result and what was printed.
+    fn grep(
+        input: &'static str,
+        exps: &[&str],
+        config: &mut Config,
+    ) -> (io::Result<Stats>, String) {
+        config.match_vals = exps.iter().map(|e| String::from(*e)).collect();
+        if !config.header {
+            config.match_exps = build_match_exps(
+                &config.match_vals,
+                &config.match_char_cfg,
+                &config.regex_cfg,
+                None,
+            )
+            .unwrap();
+        }
+        let mut out = vec![];
+        let result = svgrep(input.as_bytes(), config, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    fn input_messages(e: io::Error) -> Vec<String> {
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<InputError>())
+        {
+            Some(InputError(msgs)) => msgs.clone(),
+            None => panic!("{} is no input error", e),
+        }
+    }
+
+    #[test]
+    fn svgrep_prints_matching_rows() {
+        let (result, out) = grep("a;1\nb;2\na;3\n", &["0=a"], &mut Config::default());
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "(0) a (1) 1 \n(0) a (1) 3 \n");
+    }
+
+    #[test]
+    fn svgrep_reports_no_match() {
+        let (result, out) = grep("a;1\nb;2\n", &["1=3"], &mut Config::default());
+        assert!(!result.unwrap().matched);
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn svgrep_returns_input_errors_after_the_rows_before_them() {
+        let (result, out) = grep("a;1\n\"b;2\n", &["0=."], &mut Config::default());
+        assert_eq!(
+            input_messages(result.err().unwrap()),
+            ["Unterminated quoted field in the record starting at line 2!"]
+        );
+        assert_eq!(out, "(0) a (1) 1 \n");
+    }
+
+    #[test]
+    fn flatten_prints_rows_with_several_multi_valued_cells_as_is() {
+        let mut config = Config {
+            flatten: Some(String::from(",")),
+            ..Config::default()
+        };
+        let (result, out) = grep("a,b;c,d\ne;f,g\n", &["0=."], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "(0) a,b (1) c,d \n(0) e (1) f \n(0) e (1) g \n");
+    }
+
+    #[test]
+    fn line_range_stops_reading_after_to_line() {
+        let mut config = Config {
+            line_number: true,
+            from_line: 2,
+            to_line: Some(3),
+            ..Config::default()
+        };
+        let (result, out) = grep("a\nb\nc\n\"d\n", &["0=."], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "2:(0) b \n3:(0) c \n");
+    }
+
+    #[test]
+    fn resume_from_counts_the_lines_after_the_header() {
+        let mut config = Config {
+            header: true,
+            line_number: true,
+            resume_from: 3,
+            ..Config::default()
+        };
+        let (result, out) = grep("id;name\n1;a\n2;b\n3;c\n", &["name=."], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "3:(0) 3 (1) c \n");
+    }
+
+    #[test]
+    fn resume_from_skips_records_without_decoding_them() {
+        let mut config = Config {
+            line_number: true,
+            resume_from: 3,
+            ..Config::default()
+        };
+        config.match_vals = vec![String::from("0=.")];
+        config.match_exps = build_match_exps(
+            &config.match_vals,
+            &config.match_char_cfg,
+            &config.regex_cfg,
+            None,
+        )
+        .unwrap();
+        let input: &'static [u8] = b"\"a\nb\";1\n\xff;2\nc;3\n";
+        let mut out = vec![];
+        assert!(svgrep(input, &mut config, &mut out).unwrap().matched);
+        // The rows are numbered like --line-number numbers them.
+        assert_eq!(String::from_utf8(out).unwrap(), "3:(0) c (1) 3 \n");
+    }
+
+    #[test]
+    fn column_options_count_from_the_base() {
+        assert_eq!(build_aggregate("sum:4", 1, None).unwrap().col, 3);
+        assert_eq!(build_group_by("1,-1", 1, None).unwrap(), [0, -1]);
+        assert_eq!(build_sort("2:desc", 1, None).unwrap(), (1, true));
+        assert_eq!(build_limit_per_group("3:2", 1, None).unwrap(), (2, 2));
+        assert_eq!(build_column("1", "on", 1, None).unwrap(), 0);
+        assert_eq!(
+            build_aggregate("sum:0", 1, None).err().unwrap(),
+            "There is no column 0, the first one is 1 in --agg!"
+        );
+    }
+
+    #[test]
+    fn column_options_resolve_header_names() {
+        let mut config = Config {
+            header: true,
+            tee: true,
+            column_specs: ColumnSpecs {
+                aggregates: vec![String::from("sum:n")],
+                sort: Some(String::from("name:desc")),
+                ..ColumnSpecs::default()
+            },
+            ..Config::default()
+        };
+        let (result, out) = grep("name;n\nann;1\nbob;2\n", &["name=."], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "(0) bob (1) 2 \n(0) ann (1) 1 \n");
+        assert_eq!(config.aggregates[0].col, 1);
+        assert_eq!(config.sort, Some((0, true)));
+    }
+
+    #[test]
+    fn sort_compares_the_cells_as_trimmed_by_the_config() {
+        let mut config = Config {
+            sort: Some((0, false)),
+            ..Config::default()
+        };
+        let (_, out) = grep("a\n b\n", &["0=."], &mut config);
+        assert_eq!(out, "(0)  b \n(0) a \n");
+        config.trim = Some(Trim {
+            side: TrimSide::Both,
+            chars: None,
+        });
+        let (_, out) = grep("a\n b\n", &["0=."], &mut config);
+        assert_eq!(out, "(0) a \n(0) b \n");
+    }
+
+    #[test]
+    fn line_iter_decompresses_concatenated_gzip_members() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        let mut bytes = vec![];
+        for member in &["a;1\n", "b;2\n"] {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(member.as_bytes()).unwrap();
+            bytes.extend(encoder.finish().unwrap());
+        }
+        let path = std::env::temp_dir().join(format!("svgrep-test-{}.csv", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let lines = line_iter(path.to_str(), 16, true, false, false, Encoding::Utf8, false);
+        let texts: Vec<String> = lines
+            .unwrap()
+            .map(|l| l.unwrap().1.text().unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(texts, vec!["a;1", "b;2"]);
+    }
+
+    #[test]
+    fn follow_reopens_a_file_replaced_at_its_path() {
+        let path = std::env::temp_dir().join(format!("svgrep-follow-{}.csv", std::process::id()));
+        let rotated = path.with_extension("csv.1");
+        fs::write(&path, "a\n").unwrap();
+        let mut follow = Follow::open(path.to_str().unwrap()).unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(follow.read(&mut buf).unwrap(), 2);
+        // The new file is longer, so only its identity tells it apart.
+        fs::rename(&path, &rotated).unwrap();
+        fs::write(&path, "bbb\n").unwrap();
+        let n = follow.read(&mut buf).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+        assert_eq!(&buf[..n], b"bbb\n");
+    }
+
+    #[test]
+    fn rows_matched_by_several_expressions_are_printed_once() {
+        let mut config = Config::default();
+        let (result, out) = grep("a;b\nc;d\n", &["0=a@1", "1=b@0", "1=d@0"], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "(1) b; \n(0) c; \n");
+    }
+
+    #[test]
+    fn max_count_keeps_the_after_context() {
+        let mut config = Config {
+            max_count: Some(1),
+            after_context: 2,
+            ..Config::default()
+        };
+        let (result, out) = grep("a\na\nb\na\n", &["0=a"], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "(0) a \n(0) a \n(0) b \n");
+    }
+
+    #[test]
+    fn profile_prints_the_header_names() {
+        let mut config = Config {
+            header: true,
+            profile: true,
+            ..Config::default()
+        };
+        let (_, out) = grep("n;v\na;\nb;2\n", &["n=."], &mut config);
+        assert_eq!(
+            out,
+            "(0) n filled: 100.0% sample: a\n(1) v filled: 50.0% sample: 2\n"
+        );
+    }
+
+    #[test]
+    fn sort_by_count_orders_the_groups_by_their_rows() {
+        let mut config = Config {
+            aggregates: vec![build_aggregate("sum:1", 0, None).unwrap()],
+            group_by: vec![0],
+            sort_by_count: Some(true),
+            ..Config::default()
+        };
+        let input = "a;1\nc;2\nb;3\nc;4\n";
+        let (_, out) = grep(input, &["0=."], &mut config);
+        assert_eq!(out, "c: sum(1) = 6\na: sum(1) = 1\nb: sum(1) = 3\n");
+        config.sort_by_count = Some(false);
+        let (_, out) = grep(input, &["0=."], &mut config);
+        assert_eq!(out, "a: sum(1) = 1\nb: sum(1) = 3\nc: sum(1) = 6\n");
+    }
+
+    #[test]
+    fn rectangular_pads_and_truncates_to_the_first_row() {
+        let mut config = Config {
+            rectangular: true,
+            fill: String::from("-"),
+            ..Config::default()
+        };
+        let (result, out) = grep("a;b\nc\nd;e;f\n", &["0=."], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "(0) a (1) b \n(0) c (1) - \n(0) d (1) e \n");
+    }
+
+    #[test]
+    fn duplicate_header_names_follow_the_policy() {
+        let header = CSVRow {
+            cells: vec!["a", "b", "a", "a_2", "a"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            line: String::new(),
+            offset: 0,
+        };
+        let names = col_names(&header, DuplicateNames::First).unwrap();
+        assert_eq!((names["a"], names.len()), (0, 3));
+        assert_eq!(col_names(&header, DuplicateNames::Last).unwrap()["a"], 4);
+        let names = col_names(&header, DuplicateNames::Number).unwrap();
+        assert_eq!(
+            (names["a"], names["a_2"], names["a_3"], names["a_4"]),
+            (0, 3, 2, 4)
+        );
+        assert_eq!(
+            col_names(&header, DuplicateNames::Reject).err().unwrap(),
+            "The header name 'a' is used by columns 0 and 2!"
+        );
+    }
+
+    #[test]
+    fn invert_match_selects_the_rows_no_expression_matches() {
+        let mut config = Config {
+            invert_match: true,
+            ..Config::default()
+        };
+        let (result, out) = grep("a\nb\nc\n", &["0=a", "0=b"], &mut config);
+        assert!(result.unwrap().matched);
+        assert_eq!(out, "(0) c \n");
+    }
+
+    #[test]
+    fn cell_select_expands_ranges() {
+        let select = |s| build_cell_select(Some(s), 0, None).map(|(sel, _)| sel);
+        match select("1,3-5,9").unwrap() {
+            CellSelect::Some(cols) => assert_eq!(cols, vec![1, 3, 4, 5, 9]),
+            _ => panic!("Not a selection of columns!"),
+        }
+        match select("6-3").unwrap() {
+            CellSelect::Some(cols) => assert_eq!(cols, vec![6, 5, 4, 3]),
+            _ => panic!("Not a selection of columns!"),
+        }
+        assert!(select("1,x-3").is_err());
+    }
+
+    #[test]
+    fn lines_lose_crlf_endings_and_a_leading_bom() {
+        let read = |input: &'static [u8], keep_bom: bool| -> Vec<String> {
+            let mut reader: Box<dyn BufRead> = Box::new(input);
+            let offset = if keep_bom {
+                0
+            } else {
+                skip_bom(&mut reader).unwrap()
+            };
+            split_lines(reader, false, Encoding::Utf8, offset)
+                .map(|l| l.unwrap().1.text().unwrap())
+                .collect()
+        };
+        let input = b"\xef\xbb\xbfa;b\r\nc;d\r\ne";
+        assert_eq!(read(input, false), vec!["a;b", "c;d", "e"]);
+        assert_eq!(read(input, true), vec!["\u{feff}a;b", "c;d", "e"]);
+    }
+
+    #[test]
+    fn jobs_keep_the_order_and_row_numbers() {
+        let input: String = (0..3000).map(|i| format!("{};{}\n", i, i % 7)).collect();
+        let input: &'static str = Box::leak(input.into_boxed_str());
+        let run = |jobs| {
+            let mut config = Config {
+                jobs,
+                line_number: true,
+                ..Config::default()
+            };
+            grep(input, &["1=3"], &mut config).1
+        };
+        let sequential = run(1);
+        assert!(sequential.starts_with("4:(0) 3 (1) 3 \n11:(0) 10 (1) 3 \n"));
+        assert_eq!(run(4), sequential);
+    }
+
+    #[test]
+    fn encodings_decode_a_latin1_byte() {
+        let run = |encoding| {
+            let mut config = Config {
+                encoding,
+                match_exps: vec![MatchExp::new()],
+                ..Config::default()
+            };
+            let mut out = vec![];
+            let result = svgrep(&b"caf\xe9;1\n"[..], &mut config, &mut out);
+            result.map(|_| String::from_utf8(out).unwrap())
+        };
+        assert_eq!(run(Encoding::Latin1).unwrap(), "(0) café (1) 1 \n");
+        assert_eq!(run(Encoding::Windows1252).unwrap(), "(0) café (1) 1 \n");
+        assert_eq!(
+            run(Encoding::Utf8Lossy).unwrap(),
+            "(0) caf\u{fffd} (1) 1 \n"
+        );
+        assert!(run(Encoding::Utf8).is_err());
+    }
+
+    #[test]
+    fn union_select_displays_the_columns_of_all_matching_expressions() {
+        let mut config = Config {
+            union_select: true,
+            ..Config::default()
+        };
+        let exps = &["2=c@3,1", "0=a@2,1", "0=x@0"];
+        let (_, out) = grep("a;b;c;d\n", exps, &mut config);
+        // In the order of the expressions and their selections.
+        assert_eq!(out, "(3) d; (1) b; (2) c; \n");
+    }
+
+    #[test]
+    fn build_match_exps_reports_each_bad_expression() {
+        let vals: Vec<String> = vec!["0=(", "0=a", "1=[", "nocol", "1=b"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let msgs =
+            build_match_exps(&vals, &MatchCharCfg::default(), &RegexCfg::default(), None).err();
+        assert_eq!(msgs.map(|m| m.len()), Some(3));
+    }
+
+    #[test]
+    fn collapse_separators_merges_runs_but_keeps_quoted_empty_cells() {
+        let sep = Separator::Literal(String::from(";"));
+        let cells = |line, quoting, collapse| {
+            CSVRow::parse_line(line, &sep, quoting, collapse)
+                .unwrap()
+                .cells
+        };
+        assert_eq!(cells("a;;b", false, true), vec!["a", "b"]);
+        assert_eq!(cells("a;;b", false, false), vec!["a", "", "b"]);
+        assert_eq!(cells(";a;;\"\";b;", true, true), vec!["a", "", "b"]);
+    }
+
+    // Counts the bytes read from it into the shared counter.
+    struct Counted<R> {
+        inner: R,
+        read: Arc<Mutex<usize>>,
+    }
+
+    impl<R: Read> Read for Counted<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            *self.read.lock().unwrap() += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn quiet_stops_reading_at_the_first_match() {
+        let read = Arc::new(Mutex::new(0));
+        let input = Counted {
+            inner: io::Cursor::new("a;1\n").chain(io::repeat(b'\n').take(1 << 24)),
+            read: read.clone(),
+        };
+        let mut config = Config {
+            list_files: true,
+            match_exps: vec![MatchExp::new()],
+            ..Config::default()
+        };
+        let result = svgrep(
+            BufReader::with_capacity(64, input),
+            &mut config,
+            &mut io::sink(),
+        );
+        assert!(result.unwrap().matched);
+        assert!(*read.lock().unwrap() <= 64);
+    }
+
+    #[test]
+    fn any_separator_splits_on_each_of_its_chars() {
+        let sep = Separator::AnyOf(String::from(",;"));
+        let row = CSVRow::parse_line("a,b;c", &sep, false, false).unwrap();
+        assert_eq!(row.cells, vec!["a", "b", "c"]);
+        let row = CSVRow::parse_line("\"a;b\",c", &sep, true, false).unwrap();
+        assert_eq!(row.cells, vec!["a;b", "c"]);
+    }
+
+    #[test]
+    fn max_cell_width_truncates_displayed_cells_at_chars() {
+        let mut config = Config {
+            max_cell_width: Some(3),
+            ..Config::default()
+        };
+        // The regex still sees the whole cell.
+        let (_, out) = grep("äöüß;ab\n", &["0=ß$"], &mut config);
+        assert_eq!(out, "(0) äöü\u{2026} (1) ab \n");
+    }
+
+    #[test]
+    fn byte_offset_prefixes_the_record_start() {
+        let mut config = Config {
+            byte_offset: true,
+            line_number: true,
+            ..Config::default()
+        };
+        let (_, out) = grep("a\nbbbb;c\n\ndd", &["0=."], &mut config);
+        assert_eq!(out, "1:0:(0) a \n2:2:(0) bbbb (1) c \n4:10:(0) dd \n");
+    }
+
+    #[test]
+    fn strict_fails_at_the_first_ragged_row() {
+        let mut config = Config {
+            strict: true,
+            header: true,
+            ..Config::default()
+        };
+        let (result, _) = grep("h;i\na;b\nc;d\n", &["0=."], &mut config);
+        assert!(result.unwrap().matched);
+        let (result, out) = grep("h;i\na;b\nc\nd;e;f\n", &["0=."], &mut config);
+        assert_eq!(out, "(0) a (1) b \n");
+        assert_eq!(
+            input_messages(result.err().unwrap()),
+            vec!["Row 2 has 1 columns instead of 2!"]
+        );
+    }
+
+    #[test]
+    fn regex_size_limit_rejects_big_patterns() {
+        let regex_cfg = RegexCfg {
+            size_limit: Some(1000),
+            ..RegexCfg::default()
+        };
+        let build = |exp: &str| build_match_exp(exp, &MatchCharCfg::default(), &regex_cfg, None);
+        assert!(build("0=a").is_ok());
+        assert_eq!(
+            build("0=\\w{100}").err().unwrap(),
+            "Regex '\\w{100}' exceeds the --regex-size-limit of 1000 bytes!"
+        );
+    }
+
+    #[test]
+    fn inspect_input_reads_at_most_the_given_rows() {
+        let config = Config {
+            header: true,
+            ..Config::default()
+        };
+        // The third line isn't valid UTF-8, so decoding it would fail.
+        let input: &'static [u8] = b"name;n\nann;1;x\n\xff\n";
+        let lines = split_lines(Box::new(input), false, Encoding::Utf8, 0);
+        let mut out = vec![];
+        inspect_input(lines, 2, &config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "separator: \";\"\ncolumns: 2 to 3\nheader: name, n\nrows read: 2\n"
+        );
+    }
+
+    #[test]
+    fn negated_expressions_mix_with_plain_ones() {
+        let mut config = Config::default();
+        let input = "foo;bar\nfoo;baz\nqux;bar\n";
+        let (_, out) = grep(input, &["!0=foo&1=bar", "1=bar"], &mut config);
+        assert_eq!(
+            out,
+            "(0) foo (1) bar \n(0) foo (1) baz \n(0) qux (1) bar \n"
+        );
+        let (_, out) = grep(input, &["!0=foo&1=bar", "0=x"], &mut config);
+        assert_eq!(out, "(0) foo (1) baz \n(0) qux (1) bar \n");
+    }
+
+    #[test]
+    fn value_counts_order_ties_by_value_and_skip_missing_cells() {
+        let mut config = Config {
+            value_counts: Some(1),
+            trim: Some(Trim {
+                side: TrimSide::Both,
+                chars: None,
+            }),
+            ..Config::default()
+        };
+        let (_, out) = grep("a;y\nb;x \nc\nd; y\ne;z\n", &["0=."], &mut config);
+        assert_eq!(out, "y\t2\nx\t1\nz\t1\n");
+    }
+
+    #[test]
+    fn scan_columns_bounds_any_column_matches() {
+        let mut config = Config::default();
+        config.match_char_cfg.scan_columns = Some(5);
+        let input = "0;1;2;3;4;5;6;7;8;9;needle\n";
+        let (result, out) = grep(input, &["*=needle"], &mut config);
+        assert!(!result.unwrap().matched);
+        assert_eq!(out, "");
+        config.match_char_cfg.scan_columns = None;
+        let (result, _) = grep(input, &["*=needle"], &mut config);
+        assert!(result.unwrap().matched);
+    }
+
+    fn describe(input: &'static str, config: &Config) -> String {
+        let mut out = vec![];
+        let lines = split_lines(Box::new(input.as_bytes()), false, Encoding::Utf8, 0);
+        describe_input(lines, config, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn describe_input_prefers_more_columns_on_ties() {
+        let config = Config::default();
+        assert_eq!(
+            describe("a;\"b;c\"\nd;e;f\n", &config),
+            "{\"separator\":\";\",\"columns\":3,\"header\":null,\"quoted\":true,\"rows_sampled\":2}\n"
+        );
+        assert_eq!(
+            describe("a;b\nc;d\ne;f;g\n", &config),
+            "{\"separator\":\";\",\"columns\":2,\"header\":null,\"quoted\":false,\"rows_sampled\":3}\n"
+        );
+    }
+
+    #[test]
+    fn describe_input_prints_the_header_names() {
+        let config = Config {
+            header: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            describe("id;name\n1;ann\n", &config),
+            "{\"separator\":\";\",\"columns\":2,\"header\":[\"id\",\"name\"],\"quoted\":false,\"rows_sampled\":1}\n"
+        );
+    }
+
+    #[test]
+    fn sanitize_cells_applies_to_the_labelled_output() {
+        let mut config = Config {
+            output_separator: String::from(","),
+            sanitize_cells: Some(String::from("_")),
+            ..Config::default()
+        };
+        let (_, out) = grep("a,b;c\n", &["0=a"], &mut config);
+        assert_eq!(out, "(0) a_b (1) c \n");
+    }
+
+    #[test]
+    fn sanitize_cells_applies_to_the_template() {
+        let mut config = Config {
+            output_separator: String::from(","),
+            sanitize_cells: Some(String::from("_")),
+            template: Some(build_template("{1}: {0}", 0, None).unwrap()),
+            ..Config::default()
+        };
+        let (_, out) = grep("a,b;c\n", &["0=a"], &mut config);
+        assert_eq!(out, "c: a_b\n");
+    }
+
+    #[test]
+    fn predicates_compare_cells_as_trimmed() {
+        let matching = |exp: &str, trim: Option<Trim>| {
+            let mut config = Config::default();
+            config.regex_cfg.trim = trim;
+            let (_, out) = grep("a; ;x ;x\nb\n", &[exp], &mut config);
+            out.lines()
+                .map(|l| l[4..5].to_string())
+                .collect::<Vec<String>>()
+        };
+        let both = || {
+            Some(Trim {
+                side: TrimSide::Both,
+                chars: None,
+            })
+        };
+        assert_eq!(matching("1:is-empty", None), ["b"]);
+        assert_eq!(matching("1:is-empty", both()), ["a", "b"]);
+        assert_eq!(matching("2==@3", None), Vec::<String>::new());
+        assert_eq!(matching("2==@3", both()), ["a"]);
+        assert_eq!(matching("2=in:x", None), Vec::<String>::new());
+        assert_eq!(matching("2=in:x", both()), ["a"]);
+        let xs = Some(Trim {
+            side: TrimSide::Both,
+            chars: Some(vec!['x', ' ']),
+        });
+        assert_eq!(matching("2:is-empty", xs), ["a", "b"]);
+    }
+
+    #[test]
+    fn missing_cells_count_as_empty() {
+        let (_, out) = grep("a;b\nc\n", &["1:is-empty"], &mut Config::default());
+        assert_eq!(out, "(0) c \n");
+        let (_, out) = grep("a;b\nc\n", &["1:is-nonempty"], &mut Config::default());
+        assert_eq!(out, "(0) a (1) b \n");
+    }
+
+    #[test]
+    fn build_column_options_parse_valid_specs() {
+        let names: ColNames = vec![(String::from("a:b"), 4)].into_iter().collect();
+        let agg = build_aggregate("avg: 2", 0, None).unwrap();
+        assert!(agg.kind == AggKind::Avg && agg.col == 2);
+        assert_eq!(build_group_by("0, -1", 0, None).unwrap(), [0, -1]);
+        assert_eq!(build_sort("1", 0, None).unwrap(), (1, false));
+        assert_eq!(build_sort("a:b:desc", 0, Some(&names)).unwrap(), (4, true));
+        assert_eq!(
+            build_limit_per_group("a:b:3", 0, Some(&names)).unwrap(),
+            (4, 3)
+        );
+        assert_eq!(
+            build_column("a:b", "on-change", 0, Some(&names)).unwrap(),
+            4
+        );
+        assert_eq!(
+            build_renames("0=id,1=").unwrap(),
+            [
+                (String::from("0"), String::from("id")),
+                (String::from("1"), String::new())
+            ]
+        );
+        assert_eq!(build_join_on("1= 2").unwrap(), ("1", " 2"));
+        let reqs = build_column_reqs("0,2:int", 0, None).unwrap();
+        assert!(matches!(
+            reqs[..],
+            [
+                ColumnReq {
+                    col: 0,
+                    kind: ColType::Str
+                },
+                ColumnReq {
+                    col: 2,
+                    kind: ColType::Int
+                }
+            ]
+        ));
+        let replacement = build_replacement(r"1/a\/b/c\/d", 0, None).unwrap();
+        assert_eq!(replacement.col, 1);
+        assert_eq!(replacement.rx.as_str(), "a/b");
+        assert_eq!(replacement.replacement, "c/d");
+        assert!(matches!(
+            build_template("{{{0}}} {a:b}", 0, Some(&names)).unwrap()[..],
+            [
+                TemplatePart::Text(ref open),
+                TemplatePart::Col(0),
+                TemplatePart::Text(ref close),
+                TemplatePart::Col(4)
+            ] if open == "{" && close == "} "
+        ));
+    }
+
+    #[test]
+    fn build_column_options_reject_invalid_specs() {
+        let error = |built: Result<(), String>| built.err().unwrap();
+        assert_eq!(
+            error(build_aggregate("median:1", 0, None).map(|_| ())),
+            "'median:1' is no valid --agg <kind>:<col>!"
+        );
+        assert_eq!(
+            error(build_aggregate("sum", 0, None).map(|_| ())),
+            "'sum' is no valid --agg <kind>:<col>!"
+        );
+        assert_eq!(
+            error(build_group_by("0,x", 0, None).map(|_| ())),
+            "'x' is no valid column spec in --group-by!"
+        );
+        assert_eq!(
+            error(build_sort("1:up", 0, None).map(|_| ())),
+            "'1:up' is no valid column spec in --sort!"
+        );
+        assert_eq!(
+            error(build_sort("name", 0, Some(&ColNames::new())).map(|_| ())),
+            "There is no column named 'name' in --sort!"
+        );
+        assert_eq!(
+            error(build_limit_per_group("1", 0, None).map(|_| ())),
+            "'1' is no valid --limit-per-group <col>:<n>!"
+        );
+        assert_eq!(
+            error(build_limit_per_group("1:-2", 0, None).map(|_| ())),
+            "'1:-2' is no valid --limit-per-group <col>:<n>!"
+        );
+        assert_eq!(
+            error(build_renames("0=id,=x").map(|_| ())),
+            "'=x' is no valid --rename <col>=<name>!"
+        );
+        assert_eq!(
+            error(build_join_on("1").map(|_| ())),
+            "'1' is no valid --on <col>=<col>!"
+        );
+        assert_eq!(
+            error(build_join_on("1= ").map(|_| ())),
+            "'1= ' is no valid --on <col>=<col>!"
+        );
+        assert_eq!(
+            error(build_column_reqs("0,1:blob", 0, None).map(|_| ())),
+            "'blob' is no valid type in --require!"
+        );
+        assert_eq!(
+            error(build_replacement("1/a", 0, None).map(|_| ())),
+            "'1/a' is no valid --replace spec!"
+        );
+        assert!(error(build_replacement("1/(/x", 0, None).map(|_| ()))
+            .starts_with("Invalid regex '(' in --replace: "));
+        assert_eq!(
+            error(build_template("{0", 0, None).map(|_| ())),
+            "--template '{0' has an unclosed {!"
+        );
+        assert_eq!(
+            error(build_template("0}", 0, None).map(|_| ())),
+            "--template '0}' has an unescaped }!"
+        );
+    }
+
+    fn detected(input: &'static str) -> Option<&'static str> {
+        let mut config = Config::default();
+        let lines = split_lines(Box::new(input.as_bytes()), false, Encoding::Utf8, 0);
+        detect_separator(lines, 1024, &mut config).1
+    }
+
+    #[test]
+    fn detect_separator_prefers_consistent_then_more_columns() {
+        assert_eq!(detected("a,b,c;d\ne,f,g;h\n"), Some(","));
+        // Consistent counts beat more cells.
+        assert_eq!(detected("a,b,c;d\ne,f;g\n"), Some(";"));
+        // Ties go to the earlier candidate.
+        assert_eq!(detected("a;b|c\nd;e|f\n"), Some(";"));
+        // Quoted separators don't count.
+        assert_eq!(detected("\"a;b\",c\n\"d;e\",f\n"), Some(","));
+        assert_eq!(detected("abc\ndef\n"), None);
+    }
+
+    #[test]
+    fn detect_separator_samples_sniff_bytes_and_keeps_them() {
+        let mut config = Config::default();
+        let input = "a,b;c\nd,e;f\ng;h,i,j\n";
+        let lines = split_lines(Box::new(input.as_bytes()), false, Encoding::Utf8, 0);
+        let (lines, detected) = detect_separator(lines, 12, &mut config);
+        assert_eq!(detected, Some(","));
+        assert_eq!(lines.count(), 3);
+        let lines = split_lines(Box::new(input.as_bytes()), false, Encoding::Utf8, 0);
+        assert_eq!(detect_separator(lines, 13, &mut config).1, Some(";"));
+        let lines = split_lines(Box::new(input.as_bytes()), false, Encoding::Utf8, 0);
+        assert_eq!(detect_separator(lines, 0, &mut config).1, Some(","));
+    }
+
+    fn holds(exp: &str, line: &str) -> bool {
+        let exp =
+            build_match_exp(exp, &MatchCharCfg::default(), &RegexCfg::default(), None).unwrap();
+        let sep = Separator::Literal(String::from(";"));
+        exp.matches(&CSVRow::parse_line(line, &sep, true, false).unwrap())
+    }
+
+    fn exp_error(exp: &str) -> String {
+        build_match_exp(exp, &MatchCharCfg::default(), &RegexCfg::default(), None)
+            .err()
+            .unwrap()
+    }
+
+    #[test]
+    fn predicates_hold_on_matching_cells() {
+        assert!(holds("0:between(1,2)", "5;1;10"));
+        assert!(holds("0:between(1,2)", "1;1;10"));
+        assert!(!holds("0:between(1,2)", "11;1;10"));
+        assert!(!holds("0:between(1,2)", "x;1;10"));
+        assert!(!holds("0:between(1,2)", "5;1"));
+        assert!(holds("1:is-number", "a;-1.5e3"));
+        assert!(!holds("1:is-number", "a;1.2.3"));
+        assert!(holds("1:is-date", "a;2024-02-29"));
+        assert!(!holds("1:is-date", "a;2024-13-01"));
+        assert!(holds("1:is-email", "a;ann@example.org"));
+        assert!(!holds("1:is-email", "a;ann@example"));
+        assert!(holds("1:is-empty", "a;"));
+        assert!(!holds("1:is-nonempty", "a"));
+        assert!(holds("1=in:x, y", "a;y"));
+        assert!(!holds("1=notin:x,y", "a;y"));
+        assert!(holds("1=notin:x,y", "a"));
+        // A column containing the matches char makes an ordinary regex.
+        assert!(holds("0=x:is-empty", "x:is-empty"));
+    }
+
+    #[test]
+    fn predicates_reject_bad_arguments() {
+        assert_eq!(
+            exp_error("0:is-number(1)"),
+            "Predicate is-number takes no arguments!"
+        );
+        assert_eq!(
+            exp_error("0:between(1)"),
+            "between needs exactly two columns, e.g. 1:between(2,3)!"
+        );
+        assert_eq!(exp_error("0:nope"), "'nope' is no valid predicate!");
+        assert_eq!(exp_error("x:is-empty"), "'x' is no valid column spec!");
+    }
+
+    #[test]
+    fn same_as_compares_two_cells() {
+        assert!(holds("0==@1", "a;a"));
+        assert!(!holds("0==@1", "a;b"));
+        assert!(holds("0!=@1", "a;b"));
+        assert!(!holds("0!=@1", "a;a"));
+        // Neither holds if a cell is missing.
+        assert!(!holds("0==@1", "a"));
+        assert!(!holds("0!=@1", "a"));
+        // The @ after the cross-column clause still starts the selection.
+        assert!(holds("0==@1@0", "a;a"));
+        assert!(exp_error("0==@1@0@1").ends_with("has more than one @!"));
+    }
+
+    #[test]
+    fn disjunctions_keep_regex_alternations() {
+        assert!(holds("0=^(3|8)$", "8"));
+        assert!(!holds("0=^(3|8)$", "38"));
+        assert!(holds("0=a|b", "b"));
+        assert!(holds("0=x|1=b", "a;b"));
+        assert!(holds("0=x|y|1:is-empty", "a"));
+        assert!(!holds("0=x|y|1:is-empty", "a;b"));
+    }
+
+    #[test]
+    fn svgrep_returns_errors_in_expressions_using_the_header() {
+        let mut config = Config {
+            header: true,
+            ..Config::default()
+        };
+        let (result, _) = grep("id;name\n1;ann\n", &["nope=x"], &mut config);
+        assert_eq!(
+            input_messages(result.err().unwrap()),
+            ["There is no column named 'nope'!"]
+        );
+    }
+}
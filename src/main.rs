@@ -16,273 +16,846 @@
 // this program; if not, write to the Free Software Foundation, Inc., 51
 // Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA
 
-#[macro_use]
-extern crate lazy_static;
 extern crate clap;
 extern crate regex;
+extern crate svgrep;
 
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Lines};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, LineWriter, Write};
+use std::path::PathBuf;
 use std::process::exit;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use clap::{App, Arg, ArgMatches};
 use regex::Regex;
 
-struct CSVRow {
-    cells: Vec<String>,
-}
-
-enum CellSelect {
-    ALL,
-    Some(Vec<usize>),
-}
+use svgrep::*;
 
-struct MatchExp {
-    rxs: Vec<Regex>,
-    cell_rxs: HashMap<usize, Regex>,
-    sel: CellSelect,
+// The output is buffered in both cases; svgrep flushes it after each input.
+// With line_buffered, each line is written as soon as it ends.
+fn output_writer(file_name: Option<&str>, append: bool, line_buffered: bool) -> Box<dyn Write> {
+    let out: Box<dyn Write> = match file_name {
+        None => Box::new(io::stdout()),
+        Some(filename) => Box::new(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(filename)
+                .unwrap_or_else(|e| {
+                    error(format!("Cannot open output file {}: {}", filename, e).as_str())
+                }),
+        ),
+    };
+    if line_buffered {
+        Box::new(LineWriter::new(out))
+    } else {
+        Box::new(BufWriter::new(out))
+    }
 }
 
-struct Config {
-    separator: String,
-    trim: bool,
-    match_exps: Vec<MatchExp>,
+fn report_error(msg: &str) {
+    eprintln!("Error: {}", msg);
 }
 
-struct MatchCharCfg {
-    cell_select_char: String,
-    match_conj_char: String,
-    matches_char: String,
+fn error(msg: &str) -> ! {
+    report_error(msg);
+    exit(2);
 }
 
-impl MatchExp {
-    fn new() -> MatchExp {
-        MatchExp {
-            rxs: vec![],
-            cell_rxs: HashMap::new(),
-            sel: CellSelect::ALL,
-        }
+fn errors(msgs: &[String]) -> ! {
+    for msg in msgs {
+        report_error(msg);
     }
+    exit(2);
+}
 
-    fn match_and_select(&self, row: &CSVRow, config: &Config) {
-        let mut row_matches = self.rxs.is_empty() && self.cell_rxs.is_empty();
-
-        row_matches = row_matches
-            || self.cell_rxs.iter().all(|(cell_idx, rx)| {
-                let cell = row.get_cell(*cell_idx);
-                cell.is_some() && rx.is_match(cell.unwrap())
-            });
-        row_matches = row_matches
-            && self
-                .rxs
-                .iter()
-                .all(|rx| row.cells.iter().any(|cell| rx.is_match(cell)));
+// Returns what was built from an option's value, or exits with the message
+// why that failed.
+fn checked<T>(built: Result<T, String>) -> T {
+    built.unwrap_or_else(|msg| error(msg.as_str()))
+}
 
-        if row_matches {
-            row.print(&self.sel, config);
-        }
+// Returns the value of the string option name with the escapes of unescape
+// replaced.
+fn string_opt(opts: &ArgMatches, name: &str, default: &str) -> String {
+    match opts.value_of(name) {
+        None => String::from(default),
+        Some(s) => unescape(s),
     }
 }
 
-impl CSVRow {
-    fn from_line(line: String, sep: &str) -> CSVRow {
-        CSVRow {
-            cells: line.split(sep).map(|s| String::from(s)).collect(),
+// Returns s with the escapes \t, \n, \0, and \\ replaced.  Any other
+// backslash is kept as is.
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
         }
-    }
-
-    fn get_cell(&self, idx: usize) -> Option<&str> {
-        if idx >= self.cells.len() {
-            None
-        } else {
-            Some(self.cells[idx].as_str())
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('0') => result.push('\0'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
         }
     }
+    result
+}
 
-    fn print(&self, cols: &CellSelect, config: &Config) {
-        match cols {
-            CellSelect::ALL => {
-                for (i, cell) in self.cells.iter().enumerate() {
-                    print!("({}) {} ", i, maybe_trim(cell, config.trim));
-                }
-            }
-            CellSelect::Some(ref cols) => {
-                for i in cols {
-                    if i >= &self.cells.len() {
-                        print!("<no col {}>", i);
-                    } else {
-                        print!(
-                            "({}) {}",
-                            i,
-                            maybe_trim(self.cells[*i].as_str(), config.trim)
-                        );
+// The option defaults of a config file by long option name.  Its lines are
+// key = "string" or key = true or false, and # starts a comment line.
+struct FileDefaults {
+    values: HashMap<String, String>,
+}
+
+impl FileDefaults {
+    // Reads --config <file>, or else the optional default config file.
+    fn read(opts: &ArgMatches) -> FileDefaults {
+        let (path, required) = match opts.value_of(OPT_CONFIG) {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_config_file() {
+                Some(path) => (path, false),
+                None => {
+                    return FileDefaults {
+                        values: HashMap::new(),
                     }
-                    print!("{} ", config.separator);
                 }
+            },
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(ref e) if !required && e.kind() == io::ErrorKind::NotFound => {
+                return FileDefaults {
+                    values: HashMap::new(),
+                }
+            }
+            Err(e) => error(format!("Cannot read config file {}: {}", path.display(), e).as_str()),
+        };
+        let mut values = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
+            let fail = |msg: &str| -> ! {
+                error(format!("{}:{}: {}", path.display(), i + 1, msg).as_str())
+            };
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => fail("Expected key = value!"),
+            };
+            let value = if FILE_FLAG_OPTS.contains(&key) {
+                match value {
+                    "true" | "false" => String::from(value),
+                    _ => fail(format!("{} must be true or false!", key).as_str()),
+                }
+            } else if FILE_STRING_OPTS.contains(&key) {
+                match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                    Some(v) if !v.is_empty() => unescape(&v.replace("\\\"", "\"")),
+                    _ => fail(format!("{} must be a non-empty quoted string!", key).as_str()),
+                }
+            } else {
+                fail(format!("Unknown key {}!", key).as_str())
+            };
+            values.insert(String::from(key), value);
         }
-        println!();
+        FileDefaults { values }
     }
-}
 
-lazy_static! {
-    static ref NUMBER_RX: Regex = Regex::new(r"^\d+.*$").expect("Invalid Regex in the code!");
-    static ref ASTERISK_RX: Regex =
-        Regex::new([r"^", regex::escape("*").as_str(), "$"].join("").as_ref())
-            .expect("Invalid Regex in the code!");
-}
-
-fn maybe_trim(cell: &str, trim: bool) -> &str {
-    if trim {
-        cell.trim()
-    } else {
-        cell
+    // Returns the command line value of the string option name, or else
+    // the config file value, or else default.
+    fn string(&self, opts: &ArgMatches, name: &str, default: &str) -> String {
+        let default = self.values.get(name).map_or(default, |v| v.as_str());
+        string_opt(opts, name, default)
     }
-}
-
-fn line_iter(file_name: Option<&str>) -> Lines<Box<dyn BufRead>> {
-    let reader: Box<dyn BufRead> = match file_name {
-        None => Box::new(BufReader::new(io::stdin())),
-        Some(filename) => Box::new(BufReader::new(
-            File::open(filename).expect(format!("No file {}", filename).as_str()),
-        )),
-    };
-    reader.lines()
-}
 
-fn svgrep_lines(lines: Lines<Box<dyn BufRead>>, config: Config) {
-    let all_match = &vec![MatchExp::new()];
-    let match_exps = if config.match_exps.is_empty() {
-        all_match
-    } else {
-        &config.match_exps
-    };
-
-    for row in lines.map(|l| CSVRow::from_line(l.unwrap(), &config.separator)) {
-        for match_exp in match_exps {
-            match_exp.match_and_select(&row, &config);
-        }
+    // Returns whether the flag name is given on the command line or set in
+    // the config file.
+    fn flag(&self, opts: &ArgMatches, name: &str) -> bool {
+        opts.is_present(name) || self.values.get(name).is_some_and(|v| v == "true")
     }
 }
 
-fn error(msg: &str) {
-    eprintln!("Error: {}", msg);
-    exit(1);
+fn default_config_file() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
+    .map(|dir| dir.join(CONFIG_FILE_NAME))
 }
 
-fn build_rxs(
-    m: Option<regex::Match>,
-    match_char_cfg: &MatchCharCfg,
-) -> (Vec<Regex>, HashMap<usize, Regex>) {
-    match m {
-        None => (vec![], HashMap::new()),
-        Some(m) => {
-            let match_clauses: Vec<&str> =
-                m.as_str().split(&match_char_cfg.match_conj_char).collect();
-            let mut v = Vec::new();
-            let mut hm = HashMap::new();
-
-            for clause in match_clauses {
-                let col_and_rx: Vec<&str> = clause.split(&match_char_cfg.matches_char).collect();
-                if NUMBER_RX.is_match(col_and_rx[0]) {
-                    hm.insert(
-                        col_and_rx[0]
-                            .parse::<usize>()
-                            .expect("Invalid match column!"),
-                        Regex::new(col_and_rx[1]).expect("Invalid regex!"),
-                    );
-                } else if ASTERISK_RX.is_match(col_and_rx[0]) {
-                    v.push(Regex::new(col_and_rx[1]).expect("Invalid regex!"));
-                } else {
-                    error(format!("'{}' is no valid column spec!", col_and_rx[0]).as_str());
-                }
-            }
-
-            (v, hm)
+// Prints how the --match expressions are understood to stderr and exits
+// without reading any input but the names of the --header.
+fn explain(config: &Config, names: Option<&ColNames>) -> ! {
+    if names.is_none() {
+        eprintln!("Column names can't be resolved without --header.");
+    }
+    let exps = build_match_exps(
+        &config.match_vals,
+        &config.match_char_cfg,
+        &config.regex_cfg,
+        names,
+    )
+    .unwrap_or_else(|msgs| errors(&msgs));
+    let mut err = io::stderr();
+    for (exp, val) in exps.iter().zip(&config.match_vals) {
+        if let Err(e) = exp.explain(val, config.match_char_cfg.index_base, &mut err) {
+            error(format!("Cannot write explanation: {}", e).as_str());
         }
     }
+    let combined = if config.match_all { "all" } else { "any" };
+    eprintln!(
+        "Selects the rows matched by {} of the {} expressions.",
+        combined,
+        exps.len()
+    );
+    exit(0)
 }
 
-fn build_cell_select(s: Option<regex::Match>) -> CellSelect {
-    match s {
-        None => CellSelect::ALL,
-        Some(v) => CellSelect::Some(
-            v.as_str()
-                .split(",")
-                .map(|is| is.parse::<usize>().expect("Invalid index in select!"))
-                .collect(),
-        ),
+// Reports the error e of svgrep and exits after writing the rows selected
+// before it.
+fn fail(e: io::Error, out: &mut dyn Write) -> ! {
+    // An input error is no reason to lose the rows, a write error makes the
+    // flush fail anyway.
+    let _ = out.flush();
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<InputError>())
+    {
+        Some(InputError(msgs)) => errors(msgs),
+        None => error(format!("Cannot write output: {}", e).as_str()),
     }
 }
 
-fn build_match_exp(match_val: &str, match_char_cfg: &MatchCharCfg) -> MatchExp {
-    let rx = Regex::new(
-        [
-            r"^([^",
-            regex::escape(&match_char_cfg.cell_select_char).as_ref(),
-            "]+)?(?:",
-            regex::escape(&match_char_cfg.cell_select_char).as_ref(),
-            r"(\d+(,\d+)*))?$",
-        ]
-        .join("")
-        .as_ref(),
-    )
-    .unwrap();
-
-    let captures = rx.captures(match_val).expect("Invalid --match expression!");
-
-    let (rxs, cell_rxs) = build_rxs(captures.get(1), match_char_cfg);
-    MatchExp {
-        rxs: rxs,
-        cell_rxs: cell_rxs,
-        sel: build_cell_select(captures.get(2)),
-    }
+// Parses the value of the numeric option name, which must be at least min.
+fn usize_opt(opts: &ArgMatches, name: &str, min: usize) -> Option<usize> {
+    opts.value_of(name).map(|s| match s.parse::<usize>() {
+        Ok(n) if n >= min => n,
+        _ => error(format!("'{}' is no valid --{}!", s, name).as_str()),
+    })
 }
 
 fn build_config(opts: &ArgMatches) -> Config {
+    // Command line options take precedence over the config file.
+    let file = FileDefaults::read(opts);
+    let trim_chars = opts
+        .value_of(OPT_TRIM_CHARS)
+        .map(|_| string_opt(opts, OPT_TRIM_CHARS, ""));
+    if trim_chars.as_ref().is_some_and(|cs| cs.is_empty()) {
+        error("--trim-chars must not be empty!");
+    }
+    // --trim-chars implies --trim, and a bare --trim trims both sides.
+    let trim = if file.flag(opts, OPT_TRIM) || trim_chars.is_some() {
+        Some(Trim {
+            side: match opts.value_of(OPT_TRIM) {
+                Some("left") => TrimSide::Left,
+                Some("right") => TrimSide::Right,
+                _ => TrimSide::Both,
+            },
+            chars: trim_chars.map(|cs| cs.chars().collect()),
+        })
+    } else {
+        None
+    };
     let match_char_cfg = MatchCharCfg {
-        cell_select_char: String::from(opts.value_of(OPT_SELECT_CHAR).unwrap_or("@")),
-        match_conj_char: String::from(opts.value_of(OPT_CONJ_CHAR).unwrap_or("&")),
-        matches_char: String::from(opts.value_of(OPT_MATCHES_CHAR).unwrap_or("=")),
+        cell_select_char: file.string(opts, OPT_SELECT_CHAR, "@"),
+        match_conj_char: file.string(opts, OPT_CONJ_CHAR, "&"),
+        match_disj_char: file.string(opts, OPT_DISJ_CHAR, "|"),
+        matches_char: file.string(opts, OPT_MATCHES_CHAR, "="),
+        index_base: if opts.is_present(OPT_ONE_BASED) { 1 } else { 0 },
+        scan_columns: usize_opt(opts, OPT_SCAN_COLUMNS, 1),
     };
+    let ops = [
+        (OPT_SELECT_CHAR, &match_char_cfg.cell_select_char),
+        (OPT_CONJ_CHAR, &match_char_cfg.match_conj_char),
+        (OPT_DISJ_CHAR, &match_char_cfg.match_disj_char),
+        (OPT_MATCHES_CHAR, &match_char_cfg.matches_char),
+    ];
+    for (i, (name, op)) in ops.iter().enumerate() {
+        if op.is_empty() {
+            error(format!("--{} must not be empty!", name).as_str());
+        }
+        if let Some((other, _)) = ops[..i].iter().find(|(_, o)| o == op) {
+            error(format!("--{} and --{} must differ!", other, name).as_str());
+        }
+    }
+    let regex_cfg = RegexCfg {
+        ignore_case: opts.is_present(OPT_IGNORE_CASE),
+        fixed_strings: opts.is_present(OPT_FIXED_STRINGS),
+        word_regexp: opts.is_present(OPT_WORD_REGEXP),
+        line_regexp: opts.is_present(OPT_LINE_REGEXP),
+        anchor_columns: opts.is_present(OPT_ANCHOR_COLUMNS),
+        size_limit: usize_opt(opts, OPT_REGEX_SIZE_LIMIT, 1),
+        dfa_size_limit: usize_opt(opts, OPT_REGEX_DFA_SIZE_LIMIT, 1),
+        trim: trim.clone(),
+    };
+    if opts.is_present(OPT_JSON_ARRAY) && opts.value_of(OPT_OUTPUT_FORMAT) != Some("json") {
+        error("--json-array requires --output-format json!");
+    }
+    let has_context = [OPT_AFTER_CONTEXT, OPT_BEFORE_CONTEXT, OPT_CONTEXT]
+        .iter()
+        .any(|o| opts.is_present(o));
+    if has_context && opts.value_of(OPT_OUTPUT_FORMAT) == Some("table") {
+        error("Context rows cannot be shown with --output-format table!");
+    }
+    let from_line = usize_opt(opts, OPT_FROM_LINE, 1).unwrap_or(1);
+    let to_line = usize_opt(opts, OPT_TO_LINE, 1);
+    if to_line.is_some_and(|to| to < from_line) {
+        error("--to-line must not be less than --from-line!");
+    }
+    let header = file.flag(opts, OPT_HEADER);
+    if opts.is_present(OPT_WITH_HEADER) && !header {
+        error("--with-header requires --header!");
+    }
+    let summarized = [
+        OPT_COUNT,
+        OPT_COUNT_NONMATCHING,
+        OPT_PROFILE,
+        OPT_AGG,
+        OPT_VALUE_COUNTS,
+    ];
+    if opts.is_present(OPT_TEE) && !summarized.iter().any(|o| opts.is_present(o)) {
+        error("--tee requires --count, --count-nonmatching, --profile, --agg, or --value-counts!");
+    }
+    if opts.is_present(OPT_RENAME) && !header {
+        error("--rename requires --header!");
+    }
+    let mut match_vals: Vec<String> = opts
+        .values_of(OPT_MATCH)
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+    for path in opts.values_of(OPT_MATCH_FILE).unwrap_or_default() {
+        let content = fs::read_to_string(path)
+            .unwrap_or_else(|e| error(format!("Cannot read match file {}: {}", path, e).as_str()));
+        match_vals.extend(
+            content
+                .lines()
+                .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+                .map(String::from),
+        );
+    }
+    let separator = file.string(opts, OPT_SEPARATOR, ";");
 
-    Config {
-        separator: String::from(opts.value_of(OPT_SEPARATOR).unwrap_or(";")),
-        trim: opts.is_present(OPT_TRIM),
-        match_exps: opts
-            .values_of(OPT_MATCH)
-            .unwrap_or(clap::Values::default())
-            .map(|match_val| build_match_exp(match_val, &match_char_cfg))
-            .collect(),
+    let mut config = Config {
+        output_separator: string_opt(opts, OPT_OUTPUT_SEPARATOR, &separator),
+        separator: match opts.value_of(OPT_REGEX_SEPARATOR) {
+            // The set wins over any --separator.
+            None if opts.is_present(OPT_ANY_SEPARATOR) => {
+                let chars = string_opt(opts, OPT_ANY_SEPARATOR, "");
+                if chars.is_empty() {
+                    error("The --any-separator must not be empty!");
+                }
+                Separator::AnyOf(chars)
+            }
+            None => Separator::Literal(separator),
+            Some(rx) => {
+                let rx = Regex::new(rx).unwrap_or_else(|e| {
+                    error(format!("Invalid --regex-separator '{}': {}", rx, e).as_str())
+                });
+                if rx.is_match("") {
+                    error("The --regex-separator must not match the empty string!");
+                }
+                Separator::Regex(rx)
+            }
+        },
+        quoting: !opts.is_present(OPT_NO_QUOTING),
+        collapse_separators: opts.is_present(OPT_COLLAPSE_SEPARATORS),
+        skip_blank: opts.is_present(OPT_SKIP_BLANK),
+        null_data: opts.is_present(OPT_NULL_DATA),
+        keep_bom: opts.is_present(OPT_KEEP_BOM),
+        encoding: match opts.value_of(OPT_ENCODING) {
+            Some("latin1") => Encoding::Latin1,
+            Some("windows-1252") => Encoding::Windows1252,
+            _ if opts.is_present(OPT_LOSSY) => Encoding::Utf8Lossy,
+            _ => Encoding::Utf8,
+        },
+        comment_prefix: opts
+            .value_of(OPT_COMMENT_CHAR)
+            .map(|_| string_opt(opts, OPT_COMMENT_CHAR, "")),
+        trim,
+        flatten: opts.value_of(OPT_FLATTEN).map(|sub_sep| {
+            if sub_sep.is_empty() {
+                error("--flatten must not be empty!");
+            }
+            String::from(sub_sep)
+        }),
+        profile: opts.is_present(OPT_PROFILE),
+        renames: opts
+            .value_of(OPT_RENAME)
+            .map_or(vec![], |s| checked(build_renames(s))),
+        tee: opts.is_present(OPT_TEE),
+        stats: opts.is_present(OPT_STATS),
+        summary: opts.is_present(OPT_SUMMARY),
+        // A progress line is only useful on a terminal.
+        progress: opts.is_present(OPT_PROGRESS) && io::stderr().is_terminal(),
+        count: opts.is_present(OPT_COUNT),
+        count_nonmatching: opts.is_present(OPT_COUNT_NONMATCHING),
+        list_files: opts.is_present(OPT_FILES_WITH_MATCHES)
+            || opts.is_present(OPT_FILES_WITHOUT_MATCH)
+            // With --summary, all rows are read to count them.
+            || (opts.is_present(OPT_QUIET) && !opts.is_present(OPT_SUMMARY)),
+        invert_match: opts.is_present(OPT_INVERT_MATCH),
+        match_all: opts.is_present(OPT_MATCH_ALL),
+        union_select: opts.is_present(OPT_UNION_SELECT),
+        check_columns: opts.is_present(OPT_CHECK_COLUMNS),
+        strict: opts.is_present(OPT_STRICT),
+        rectangular: opts.is_present(OPT_RECTANGULAR),
+        fill: String::from(opts.value_of(OPT_FILL).unwrap_or("")),
+        column_count: usize_opt(opts, OPT_CHECK_COLUMNS, 1),
+        resume_from: usize_opt(opts, OPT_RESUME_FROM_LINE, 1).unwrap_or(1),
+        index_base: usize_opt(opts, OPT_INDEX_BASE, 0).unwrap_or(match_char_cfg.index_base),
+        index_by_selection: opts.is_present(OPT_INDEX_BY_SELECTION),
+        no_index: opts.is_present(OPT_NO_INDEX),
+        no_trailing_separator: opts.is_present(OPT_NO_TRAILING_SEPARATOR),
+        missing_cell: match opts.value_of(OPT_MISSING_CELL) {
+            Some("placeholder") => MissingCell::Placeholder,
+            Some("empty") => MissingCell::Empty,
+            Some("skip") => MissingCell::Skip,
+            // Only the labelled pretty output defaults to the placeholder.
+            _ if matches!(opts.value_of(OPT_OUTPUT_FORMAT), None | Some("pretty"))
+                && !opts.is_present(OPT_NO_INDEX)
+                && !opts.is_present(OPT_TEMPLATE) =>
+            {
+                MissingCell::Placeholder
+            }
+            _ => MissingCell::Empty,
+        },
+        max_cell_width: usize_opt(opts, OPT_MAX_CELL_WIDTH, 1),
+        sanitize_cells: opts.value_of(OPT_SANITIZE_CELLS).map(String::from),
+        header,
+        duplicate_names: match opts.value_of(OPT_DUPLICATE_NAMES) {
+            Some("last") => DuplicateNames::Last,
+            Some("number") => DuplicateNames::Number,
+            Some("reject") => DuplicateNames::Reject,
+            _ => DuplicateNames::First,
+        },
+        with_header: opts.is_present(OPT_WITH_HEADER),
+        line_number: opts.is_present(OPT_LINE_NUMBER),
+        byte_offset: opts.is_present(OPT_BYTE_OFFSET),
+        max_count: usize_opt(opts, OPT_MAX_COUNT, 0),
+        tail: usize_opt(opts, OPT_TAIL, 1),
+        every: usize_opt(opts, OPT_EVERY, 1).unwrap_or(1),
+        sample: opts.value_of(OPT_SAMPLE).map(|s| match s.parse::<f64>() {
+            Ok(rate) if rate > 0.0 && rate <= 1.0 => rate,
+            _ => error(format!("'{}' is no valid --sample between 0 and 1!", s).as_str()),
+        }),
+        seed: match opts.value_of(OPT_SEED) {
+            Some(s) => s
+                .parse::<u64>()
+                .unwrap_or_else(|_| error(format!("'{}' is no valid --seed!", s).as_str())),
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64),
+        },
+        numeric_sort: opts.is_present(OPT_NUMERIC_SORT),
+        sort_by_count: Some(!opts.is_present(OPT_REVERSE_COUNT))
+            .filter(|_| opts.is_present(OPT_SORT_BY_COUNT)),
+        jobs: usize_opt(opts, OPT_JOBS, 1).unwrap_or(1),
+        from_line,
+        to_line,
+        before_context: usize_opt(opts, OPT_BEFORE_CONTEXT, 0)
+            .or(usize_opt(opts, OPT_CONTEXT, 0))
+            .unwrap_or(0),
+        after_context: usize_opt(opts, OPT_AFTER_CONTEXT, 0)
+            .or(usize_opt(opts, OPT_CONTEXT, 0))
+            .unwrap_or(0),
+        only_matching: opts.is_present(OPT_ONLY_MATCHING),
+        color: match opts.value_of(OPT_COLOR) {
+            Some("always") => true,
+            Some("never") => false,
+            _ => opts.value_of(OPT_OUTPUT).is_none() && io::stdout().is_terminal(),
+        },
+        output_format: match opts.value_of(OPT_OUTPUT_FORMAT) {
+            Some("csv") => OutputFormat::Csv,
+            Some("tsv") => OutputFormat::Tsv,
+            Some("json") => OutputFormat::Json,
+            Some("table") => OutputFormat::Table,
+            Some("ndjson-matches") => OutputFormat::NdjsonMatches,
+            _ => OutputFormat::Pretty,
+        },
+        json_array: opts.is_present(OPT_JSON_ARRAY),
+        unique: opts.is_present(OPT_UNIQUE),
+        // With --header, the expressions are built once the names are known,
+        // with --explain by explain.
+        match_exps: if header || opts.is_present(OPT_EXPLAIN) {
+            vec![]
+        } else {
+            build_match_exps(&match_vals, &match_char_cfg, &regex_cfg, None)
+                .unwrap_or_else(|msgs| errors(&msgs))
+        },
+        template: opts.value_of(OPT_TEMPLATE).filter(|_| !header).map(|spec| {
+            build_template(spec, match_char_cfg.index_base, None)
+                .unwrap_or_else(|msg| error(msg.as_str()))
+        }),
+        template_spec: opts.value_of(OPT_TEMPLATE).map(String::from),
+        match_char_cfg,
+        regex_cfg,
+        match_vals,
+        column_specs: ColumnSpecs {
+            aggregates: opts
+                .values_of(OPT_AGG)
+                .map_or(vec![], |specs| specs.map(String::from).collect()),
+            group_by: opts.value_of(OPT_GROUP_BY).map(String::from),
+            value_counts: opts.value_of(OPT_VALUE_COUNTS).map(String::from),
+            require: opts.value_of(OPT_REQUIRE).map(String::from),
+            replacements: opts
+                .values_of(OPT_REPLACE)
+                .map_or(vec![], |specs| specs.map(String::from).collect()),
+            limit_per_group: opts.value_of(OPT_LIMIT_PER_GROUP).map(String::from),
+            on_change: opts.value_of(OPT_ON_CHANGE).map(String::from),
+            sort: opts.value_of(OPT_SORT).map(String::from),
+            join_col: opts
+                .value_of(OPT_ON)
+                .map(|s| String::from(checked(build_join_on(s)).0)),
+        },
+        ..Config::default()
+    };
+    // With --header, the columns are built once the names are known.
+    if !header {
+        config
+            .build_columns(None)
+            .unwrap_or_else(|msgs| errors(&msgs));
     }
+    config
 }
 
 fn main() {
     let opts = parse_command_line();
-    let config = build_config(&opts);
+    let mut config = build_config(&opts);
+    let buffer_size = usize_opt(&opts, OPT_BUFFER_SIZE, 1).unwrap_or(DEFAULT_BUFFER_SIZE);
+    let sniff_bytes = usize_opt(&opts, OPT_SNIFF_BYTES, 1).unwrap_or(DEFAULT_SNIFF_BYTES);
+    if opts.is_present(OPT_EXPLAIN) {
+        // The names are those of the first input file's header.
+        let names = Some(opts.value_of(OPT_FILE).filter(|n| *n != "-"))
+            .filter(|_| config.header)
+            .map(|file_name| {
+                let display_name = file_name.unwrap_or(STDIN_NAME);
+                line_iter(
+                    file_name,
+                    buffer_size,
+                    !opts.is_present(OPT_NO_DECOMPRESS),
+                    config.null_data,
+                    config.keep_bom,
+                    config.encoding,
+                    false,
+                )
+                .and_then(|lines| read_col_names(lines, &config))
+                .unwrap_or_else(|e| {
+                    error(format!("Cannot read the header of {}: {}", display_name, e).as_str())
+                })
+            });
+        explain(&config, names.as_ref());
+    }
 
-    let lines = line_iter(opts.value_of(OPT_FILE));
-    svgrep_lines(lines, config);
+    if let Some(join_file) = opts.value_of(OPT_JOIN) {
+        let join = read_join(
+            join_file,
+            buffer_size,
+            opts.value_of(OPT_ON).map(|s| checked(build_join_on(s)).1),
+            opts.value_of(OPT_JOIN_TYPE) == Some("left"),
+            opts.value_of(OPT_JOIN_DUPLICATES) == Some("all"),
+            &config,
+        )
+        .unwrap_or_else(|e| {
+            error(format!("Cannot read --join file {}: {}", join_file, e).as_str())
+        });
+        config.join = Some(Rc::new(join));
+    }
+    let file_names: Vec<Option<&str>> = match opts.values_of(OPT_FILE) {
+        None => vec![None],
+        // Like in most Unix tools, - stands for stdin.
+        Some(names) => names.map(|name| Some(name).filter(|n| *n != "-")).collect(),
+    };
+    let with_filename = !opts.is_present(OPT_NO_FILENAME)
+        && (file_names.len() > 1 || opts.is_present(OPT_WITH_FILENAME));
+    let follow = opts.is_present(OPT_FOLLOW);
+    if follow && (file_names.len() != 1 || file_names[0].is_none()) {
+        error("--follow requires a single input file!");
+    }
+    // Followed rows are printed as they arrive rather than when the buffer
+    // fills.
+    let mut out = if opts.is_present(OPT_QUIET) {
+        // Only the --summary on stderr remains.
+        Box::new(io::sink())
+    } else {
+        output_writer(
+            opts.value_of(OPT_OUTPUT),
+            opts.is_present(OPT_APPEND),
+            follow,
+        )
+    };
+    let mut failed = false;
+    let mut matched = false;
+    let mut violations = 0;
+    for file_name in file_names {
+        let display_name = file_name.unwrap_or(STDIN_NAME);
+        let lines = match line_iter(
+            file_name,
+            buffer_size,
+            !opts.is_present(OPT_NO_DECOMPRESS),
+            config.null_data,
+            config.keep_bom,
+            config.encoding,
+            follow,
+        ) {
+            Ok(lines) => lines,
+            Err(e) => {
+                report_error(format!("Cannot open input file {}: {}", display_name, e).as_str());
+                failed = true;
+                continue;
+            }
+        };
+        if with_filename {
+            config.file_name = Some(String::from(display_name));
+        }
+        let lines = if opts.is_present(OPT_DETECT_SEPARATOR) {
+            let (lines, detected) = detect_separator(lines, sniff_bytes, &mut config);
+            match detected {
+                Some(sep) => eprintln!("Detected separator {:?} in {}", sep, display_name),
+                None => eprintln!(
+                    "Detected no separator in {}, using {:?}",
+                    display_name,
+                    config.separator.as_str()
+                ),
+            }
+            lines
+        } else {
+            lines
+        };
+        let result = if opts.is_present(OPT_INSPECT) {
+            let max_rows = usize_opt(&opts, OPT_INSPECT, 1).unwrap_or(1);
+            inspect_input(lines, max_rows, &config, &mut *out).map(|()| Stats {
+                matched: true,
+                violations: 0,
+            })
+        } else if opts.is_present(OPT_DESCRIBE_INPUT) {
+            describe_input(lines, &config, &mut *out).map(|()| Stats {
+                matched: true,
+                violations: 0,
+            })
+        } else {
+            svgrep_lines(lines, &mut config, &mut *out)
+        };
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return,
+            Err(e) => fail(e, &mut *out),
+            Ok(outcome) => {
+                let listed = if outcome.matched {
+                    opts.is_present(OPT_FILES_WITH_MATCHES)
+                } else {
+                    opts.is_present(OPT_FILES_WITHOUT_MATCH)
+                };
+                if listed {
+                    match writeln!(out, "{}", display_name).and_then(|()| out.flush()) {
+                        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return,
+                        Err(e) => error(format!("Cannot write output: {}", e).as_str()),
+                        Ok(()) => (),
+                    }
+                }
+                matched = matched || outcome.matched;
+                violations += outcome.violations;
+            }
+        }
+        if matched && opts.is_present(OPT_QUIET) && !opts.is_present(OPT_SUMMARY) {
+            break;
+        }
+        // An interrupted run is resumed within the first file only.
+        config.resume_from = 1;
+    }
+    if config.json_array && !opts.is_present(OPT_QUIET) {
+        let close = if config.json_rows.get() == 0 {
+            "[]"
+        } else {
+            "]"
+        };
+        match writeln!(out, "{}", close).and_then(|()| out.flush()) {
+            Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return,
+            Err(e) => error(format!("Cannot write output: {}", e).as_str()),
+            Ok(()) => (),
+        }
+    }
+    // Like grep, but --require and --check-columns only fail on violations.
+    if failed {
+        exit(2);
+    } else if violations > 0 || (!matched && !opts.is_present(OPT_REQUIRE) && !config.check_columns)
+    {
+        exit(1);
+    }
 }
 
-const OPT_FILE: &'static str = "FILE";
-const OPT_SEPARATOR: &'static str = "separator";
-const OPT_MATCH: &'static str = "match";
-const OPT_CONJ_CHAR: &'static str = "conj-char";
-const OPT_SELECT_CHAR: &'static str = "cell-select-char";
-const OPT_MATCHES_CHAR: &'static str = "matches-char";
-const OPT_TRIM: &'static str = "trim";
-const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
+const OPT_FILE: &str = "FILE";
+const OPT_SEPARATOR: &str = "separator";
+const OPT_REGEX_SEPARATOR: &str = "regex-separator";
+const OPT_ANY_SEPARATOR: &str = "any-separator";
+const OPT_DETECT_SEPARATOR: &str = "detect-separator";
+const OPT_SNIFF_BYTES: &str = "sniff-bytes";
+const DEFAULT_SNIFF_BYTES: usize = 4096;
+const OPT_OUTPUT_SEPARATOR: &str = "output-separator";
+const OPT_MATCH: &str = "match";
+const OPT_MATCH_FILE: &str = "match-file";
+const OPT_CONJ_CHAR: &str = "conj-char";
+const OPT_DISJ_CHAR: &str = "disj-char";
+const OPT_SELECT_CHAR: &str = "cell-select-char";
+const OPT_MATCHES_CHAR: &str = "matches-char";
+const OPT_TRIM: &str = "trim";
+const OPT_TRIM_CHARS: &str = "trim-chars";
+const OPT_IGNORE_CASE: &str = "ignore-case";
+const OPT_FIXED_STRINGS: &str = "fixed-strings";
+const OPT_WORD_REGEXP: &str = "word-regexp";
+const OPT_LINE_REGEXP: &str = "line-regexp";
+const OPT_ANCHOR_COLUMNS: &str = "anchor-columns";
+const OPT_REGEX_SIZE_LIMIT: &str = "regex-size-limit";
+const OPT_REGEX_DFA_SIZE_LIMIT: &str = "regex-dfa-size-limit";
+const OPT_HEADER: &str = "header";
+const OPT_WITH_HEADER: &str = "with-header";
+const OPT_RENAME: &str = "rename";
+const OPT_INVERT_MATCH: &str = "invert-match";
+const OPT_MATCH_ALL: &str = "match-all";
+const OPT_UNION_SELECT: &str = "union-select";
+const OPT_EXPLAIN: &str = "explain";
+const OPT_NO_QUOTING: &str = "no-quoting";
+const OPT_COLLAPSE_SEPARATORS: &str = "collapse-separators";
+const OPT_SKIP_BLANK: &str = "skip-blank";
+const OPT_COMMENT_CHAR: &str = "comment-char";
+const OPT_NULL_DATA: &str = "null-data";
+const OPT_KEEP_BOM: &str = "keep-bom";
+const OPT_ENCODING: &str = "encoding";
+const OPT_LOSSY: &str = "lossy";
+const OPT_FLATTEN: &str = "flatten";
+const OPT_PROFILE: &str = "profile";
+const OPT_AGG: &str = "agg";
+const OPT_VALUE_COUNTS: &str = "value-counts";
+const OPT_GROUP_BY: &str = "group-by";
+const OPT_INDEX_BASE: &str = "output-index-base";
+const OPT_ONE_BASED: &str = "one-based";
+const OPT_SCAN_COLUMNS: &str = "scan-columns";
+const OPT_INDEX_BY_SELECTION: &str = "index-by-selection";
+const OPT_NO_INDEX: &str = "no-index";
+const OPT_NO_TRAILING_SEPARATOR: &str = "no-trailing-separator";
+const OPT_MISSING_CELL: &str = "missing-cell";
+const OPT_DUPLICATE_NAMES: &str = "duplicate-names";
+const OPT_MAX_CELL_WIDTH: &str = "max-cell-width";
+const OPT_SANITIZE_CELLS: &str = "sanitize-cells";
+const OPT_BUFFER_SIZE: &str = "buffer-size";
+const OPT_JOBS: &str = "jobs";
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+const OPT_NO_DECOMPRESS: &str = "no-decompress";
+const OPT_FOLLOW: &str = "follow";
+const OPT_LIMIT_PER_GROUP: &str = "limit-per-group";
+const OPT_ON_CHANGE: &str = "on-change";
+const OPT_DESCRIBE_INPUT: &str = "describe-input";
+const OPT_INSPECT: &str = "inspect";
+const OPT_TEE: &str = "tee";
+const OPT_STATS: &str = "stats";
+const OPT_SUMMARY: &str = "summary";
+const OPT_PROGRESS: &str = "progress";
+const OPT_COUNT: &str = "count";
+const OPT_COUNT_NONMATCHING: &str = "count-nonmatching";
+const OPT_FILES_WITH_MATCHES: &str = "files-with-matches";
+const OPT_FILES_WITHOUT_MATCH: &str = "files-without-match";
+const OPT_QUIET: &str = "quiet";
+const OPT_REQUIRE: &str = "require";
+const OPT_REPLACE: &str = "replace";
+const OPT_CHECK_COLUMNS: &str = "check-columns";
+const OPT_STRICT: &str = "strict";
+const OPT_RECTANGULAR: &str = "rectangular";
+const OPT_FILL: &str = "fill";
+const OPT_RESUME_FROM_LINE: &str = "resume-from-line";
+const OPT_OUTPUT: &str = "output";
+const OPT_CONFIG: &str = "config";
+const CONFIG_FILE_NAME: &str = "svgrep.toml";
+const FILE_STRING_OPTS: [&str; 5] = [
+    OPT_SEPARATOR,
+    OPT_SELECT_CHAR,
+    OPT_CONJ_CHAR,
+    OPT_DISJ_CHAR,
+    OPT_MATCHES_CHAR,
+];
+const FILE_FLAG_OPTS: [&str; 2] = [OPT_TRIM, OPT_HEADER];
+const OPT_APPEND: &str = "append";
+const OPT_LINE_NUMBER: &str = "line-number";
+const OPT_BYTE_OFFSET: &str = "byte-offset";
+const OPT_MAX_COUNT: &str = "max-count";
+const OPT_TAIL: &str = "tail";
+const OPT_EVERY: &str = "every";
+const OPT_SAMPLE: &str = "sample";
+const OPT_SEED: &str = "seed";
+const OPT_SORT: &str = "sort";
+const OPT_JOIN: &str = "join";
+const OPT_ON: &str = "on";
+const OPT_JOIN_TYPE: &str = "join-type";
+const OPT_JOIN_DUPLICATES: &str = "join-duplicates";
+const OPT_SORT_BY_COUNT: &str = "sort-by-count";
+const OPT_REVERSE_COUNT: &str = "reverse-count";
+const OPT_NUMERIC_SORT: &str = "numeric-sort";
+const OPT_FROM_LINE: &str = "from-line";
+const OPT_TO_LINE: &str = "to-line";
+const OPT_AFTER_CONTEXT: &str = "after-context";
+const OPT_BEFORE_CONTEXT: &str = "before-context";
+const OPT_CONTEXT: &str = "context";
+const OPT_ONLY_MATCHING: &str = "only-matching";
+const OPT_COLOR: &str = "color";
+const OPT_OUTPUT_FORMAT: &str = "output-format";
+const OPT_TEMPLATE: &str = "template";
+const OPT_JSON_ARRAY: &str = "json-array";
+const OPT_UNIQUE: &str = "unique";
+const OPT_WITH_FILENAME: &str = "with-filename";
+const OPT_NO_FILENAME: &str = "no-filename";
+const STDIN_NAME: &str = "(standard input)";
+const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
+
+// Passes the values of -m and --match as --match=<value> so that clap
+// doesn't take values starting with a negative column like -1=x for flags.
+// A multiple option allowing hyphen values would swallow the flags after it.
+fn match_hyphen_values<I: Iterator<Item = String>>(args: I) -> Vec<String> {
+    let mut result = vec![];
+    // The -m or --match whose values are read, kept until one follows.
+    let mut option: Option<String> = None;
+    let mut in_match = false;
+    for arg in args {
+        let negative_col = arg.len() > 1 && arg[1..].starts_with(|c: char| c.is_ascii_digit());
+        if in_match && (!arg.starts_with('-') || negative_col) {
+            option = None;
+            result.push(format!("--{}={}", OPT_MATCH, arg));
+            continue;
+        }
+        result.extend(option.take());
+        in_match = arg == "-m" || arg == "--match";
+        if in_match {
+            option = Some(arg);
+        } else {
+            result.push(arg);
+        }
+    }
+    result.extend(option);
+    result
+}
 
 fn parse_command_line<'a>() -> ArgMatches<'a> {
     App::new("svgrep -- Separated Values Grep")
         .version(VERSION.unwrap_or("<version unknown>"))
         .about("Greps and extracts cells of CSV/TSV/*SV files")
         .author("Tassilo Horn <tsdh@gnu.org>")
+        .after_help("The exit status is 0 if any row was selected, 1 if none was, and 2 on errors.")
         .arg(
             Arg::with_name(OPT_FILE)
-                .help("The separated values file. If none is given, reads from stdin.")
+                .help(format!("{}\n{}",
+                              "The separated values files, processed in order.  If none is given or",
+                              "for -, reads from stdin.").as_str())
+                .multiple(true)
                 .required(false),
         )
         .arg(
@@ -291,8 +864,50 @@ fn parse_command_line<'a>() -> ArgMatches<'a> {
                 .long(OPT_SEPARATOR)
                 .takes_value(true)
                 .value_name("char")
-                .help("Sets the separator to be used (default: ';')"),
+                .help(format!("{}\n{}\n{}",
+                              "Sets the separator to be used (default: ';').  \\t, \\n, \\0, and \\\\",
+                              "stand for a tab, a newline, NUL, and a backslash in this and the other",
+                              "separator and char options.").as_str()),
         )
+        .arg(Arg::with_name(OPT_REGEX_SEPARATOR)
+             .long(OPT_REGEX_SEPARATOR)
+             .takes_value(true)
+             .value_name("regex")
+             .conflicts_with(OPT_SEPARATOR)
+             .help(format!("{}\n{}",
+                           "Splits the cells at each match of <regex>, e.g., \\s+ for runs of",
+                           "whitespace.  Printing still uses the --output-separator (default: ';').").as_str()))
+        .arg(Arg::with_name(OPT_ANY_SEPARATOR)
+             .long(OPT_ANY_SEPARATOR)
+             .takes_value(true)
+             .value_name("chars")
+             .conflicts_with(OPT_REGEX_SEPARATOR)
+             .help(format!("{}\n{}",
+                           "Splits the cells at each one of <chars>, e.g., ',;', overriding --separator.",
+                           "Printing still uses the --output-separator (default: the --separator).").as_str()))
+        .arg(Arg::with_name(OPT_DETECT_SEPARATOR)
+             .long(OPT_DETECT_SEPARATOR)
+             .conflicts_with_all(&[OPT_SEPARATOR, OPT_REGEX_SEPARATOR, OPT_ANY_SEPARATOR])
+             .help(format!("{}\n{}\n{}",
+                           "Uses the one of , ; tab | which splits the first lines of each input into",
+                           "the same, highest number of cells (the earlier on ties), and reports it on",
+                           "stderr.  The --output-separator follows unless given.").as_str()))
+        .arg(Arg::with_name(OPT_SNIFF_BYTES)
+             .long(OPT_SNIFF_BYTES)
+             .takes_value(true)
+             .value_name("bytes")
+             .requires(OPT_DETECT_SEPARATOR)
+             .help(format!("{}\n{}\n{}",
+                           "Makes --detect-separator sample the lines in the first <bytes> of each",
+                           "(decompressed) input, at least one (default: 4096).  More bytes guess more",
+                           "reliably but take longer.  The sampled lines are still processed.").as_str()))
+        .arg(Arg::with_name(OPT_OUTPUT_SEPARATOR)
+             .long(OPT_OUTPUT_SEPARATOR)
+             .takes_value(true)
+             .value_name("str")
+             .help(format!("{}\n{}",
+                           "Sets the separator between printed cells of the pretty and csv",
+                           "--output-format (default: the --separator).").as_str()))
         .arg(
             Arg::with_name(OPT_MATCH)
                 .short("m")
@@ -300,45 +915,759 @@ fn parse_command_line<'a>() -> ArgMatches<'a> {
                 .takes_value(true)
                 .multiple(true)
                 .help(
-                    format!(
-                        "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+                    [
                         "Sets the match-and-select expression.\n",
-                        "Syntax:\n<col>=<regex>(&<col>=<regex>)+@<disp_cols>",
-                        "<col> is a natural number or * meaning any column.",
+                        "Syntax:\n<col>=<regex>([&|]<col>=<regex>)+@<disp_cols>",
+                        "<col> is a natural number, a negative number counting from the end (-1 is",
+                        "the last column), a column name if --header is given, or * meaning any",
+                        "column, e.g., -m -1=ERROR.  The column ~ is the record as read, i.e., with",
+                        "separators, quotes, and the line breaks of records spanning lines, e.g.,",
+                        "~=a;b.",
                         "<regex> is a regex matched against the cells at column <col>.",
+                        "<col>!=<regex> holds if the cell doesn't match or is missing, *!=<regex>",
+                        "if no cell matches.",
+                        "Instead of <col>=<regex>, a clause may be a predicate <col>:<pred>.",
+                        "<col>:between(<lo>,<hi>) holds if the number in <col> lies within the",
+                        "numbers in the columns <lo> and <hi> (inclusive).",
+                        "<col>:is-number, <col>:is-date (ISO 8601), and <col>:is-email hold if",
+                        "the cell in <col> is a valid number, date, or email address.",
+                        "<col>:is-empty holds if the cell in <col> is empty or missing,",
+                        "<col>:is-nonempty if it isn't.",
+                        "<col>=in:<v1>,<v2>,... holds if the cell in <col> is one of the given",
+                        "values, <col>=notin:<v1>,<v2>,... if it is none of them.",
+                        "<col>==@<col2> holds if the cells in <col> and <col2> are equal,",
+                        "<col>!=@<col2> if they differ.  Predicates compare the cells as trimmed",
+                        "by --trim and --trim-chars, or as read without them.",
                         "<disp_cols> is a comma-separated list of columns to display (defaul: all).",
+                        "An entry <n>-<m> stands for the columns <n> to <m>, in reverse order if",
+                        "<m> is less than <n>.  A leading ^ displays all columns except the listed",
+                        "ones, e.g., @^0,3-5.  An entry <n>:<g> displays capture group <g> of the",
+                        "first regex clause matching the cell in <n> (empty if none does), e.g.,",
+                        "2=user_(\\w+)@2:1.  Columns may be listed repeatedly and in any order,",
+                        "e.g., @2,0,2 displays three cells.  @! displays the cells no regex clause",
+                        "matches, or all cells of rows without such a match.",
+                        "A backslash before one of the =, &, |, and @ strings makes it part of <regex>,",
+                        "e.g., 1=foo\\|bar for a regex alternation.",
                         "\n--match '1=foo&2=bar' acts as logical AND wheras multiple expressions like",
-                        "--match '1=foo' '2=bar' act as a logical OR."
-                    ).as_str(),
+                        "--match '1=foo' '2=bar' act as a logical OR.  A row matched by several",
+                        "expressions is printed once with the <disp_cols> of the first of them.",
+                        "Within an expression, | is a logical OR binding tighter than &, i.e.,",
+                        "'1=a|1=b&2=c' means (1=a OR 1=b) AND 2=c.  A | which isn't followed by a",
+                        "clause belongs to the regex, e.g., '1=a|b' and '1=^(a|b)$'.  A leading !",
+                        "negates the whole expression, e.g., '!1=foo&2=bar' means NOT (1=foo AND",
+                        "2=bar), so negated and plain expressions may be mixed, e.g., --match '!1=foo'",
+                        "'2=bar'."
+                    ].join("\n").as_str(),
                 ),
         )
+        .arg(Arg::with_name(OPT_MATCH_FILE)
+             .short("f")
+             .long(OPT_MATCH_FILE)
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("file")
+             .help(format!("{}\n{}",
+                           "Reads additional --match expressions from <file>, one per line.  Blank",
+                           "lines and lines starting with # are ignored.").as_str()))
+        .arg(Arg::with_name(OPT_SCAN_COLUMNS)
+             .long(OPT_SCAN_COLUMNS)
+             .takes_value(true)
+             .value_name("k")
+             .help(format!("{}\n{}",
+                           "Matches *=<regex> clauses only against the first <k> cells, e.g., to skip",
+                           "a trailing notes column or to speed up matching very wide rows.").as_str()))
         .arg(Arg::with_name(OPT_MATCHES_CHAR)
              .short("=")
              .long(OPT_MATCHES_CHAR)
              .takes_value(true)
-             .value_name("char")
+             .value_name("str")
              .help(format!("{}\n{}",
-                           "Separates a <col> from the <regex> in --match expressions.",
-                           "(default: =).").as_str()))
+                           "Separates a <col> from the <regex> in --match expressions (default: =).",
+                           "This and the other *-char options may be several chars, e.g., :=:.").as_str()))
         .arg(Arg::with_name(OPT_CONJ_CHAR)
              .short("&")
              .long(OPT_CONJ_CHAR)
              .takes_value(true)
-             .value_name("char")
+             .value_name("str")
              .help(format!("{}\n{}",
                            "Separates multiple <col>=<regex> pairs in --match expressions",
                            "to form a conjunction (default: &).").as_str()))
+        .arg(Arg::with_name(OPT_DISJ_CHAR)
+             .short("|")
+             .long(OPT_DISJ_CHAR)
+             .takes_value(true)
+             .value_name("str")
+             .help(format!("{}\n{}",
+                           "Separates alternative <col>=<regex> pairs in --match expressions",
+                           "to form a disjunction (default: |).  Elsewhere it belongs to the regex.").as_str()))
         .arg(Arg::with_name(OPT_SELECT_CHAR)
              .short("@")
              .long(OPT_SELECT_CHAR)
              .takes_value(true)
-             .value_name("char")
+             .value_name("str")
              .help(format!("{}\n{}",
                            "Separates the <col>=<regex> pairs in --match expressions from",
                            "the column display selection (default: @).").as_str()))
         .arg(Arg::with_name(OPT_TRIM)
              .short("t")
              .long(OPT_TRIM)
-             .help("Trim the cell contents when printing."))
-        .get_matches()
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("side")
+             .possible_values(&["left", "right", "both"])
+             .help(format!("{}\n{}",
+                           "Trim the cell contents when printing, on both sides unless --trim=left or",
+                           "--trim=right is given.").as_str()))
+        .arg(Arg::with_name(OPT_TRIM_CHARS)
+             .long(OPT_TRIM_CHARS)
+             .takes_value(true)
+             .value_name("chars")
+             .help(format!("{}\n{}",
+                           "Makes --trim remove the given chars instead of whitespace, e.g., '0 '.",
+                           "Implies --trim.").as_str()))
+        .arg(Arg::with_name(OPT_IGNORE_CASE)
+             .short("i")
+             .long(OPT_IGNORE_CASE)
+             .help(format!("{}\n{}",
+                           "Matches all regexes case-insensitively.  An inline (?-i) in a regex",
+                           "turns it off again for the rest of that group.").as_str()))
+        .arg(Arg::with_name(OPT_FIXED_STRINGS)
+             .short("F")
+             .long(OPT_FIXED_STRINGS)
+             .help(format!("{}\n{}",
+                           "Treats each <regex> as a literal string to be contained in the cell, e.g.,",
+                           "1=192.168.0.1 doesn't match 192x168x0x1.").as_str()))
+        .arg(Arg::with_name(OPT_WORD_REGEXP)
+             .short("w")
+             .long(OPT_WORD_REGEXP)
+             .help(format!("{}\n{}",
+                           "Matches each <regex> only as a whole word within the cell, e.g., 1=cat",
+                           "doesn't match category.").as_str()))
+        .arg(Arg::with_name(OPT_LINE_REGEXP)
+             .short("x")
+             .long(OPT_LINE_REGEXP)
+             .alias("exact")
+             .help(format!("{}\n{}",
+                           "Matches each <regex> only against the whole cell, e.g., 1=cat doesn't match",
+                           "the cat.  With --trim, whitespace around the cell is ignored.").as_str()))
+        .arg(Arg::with_name(OPT_ANCHOR_COLUMNS)
+             .long(OPT_ANCHOR_COLUMNS)
+             .help(format!("{}\n{}",
+                           "Like --line-regexp, but only for clauses with a column, e.g., 1=cat, while",
+                           "*=<regex> and ~=<regex> clauses still match substrings.").as_str()))
+        .arg(Arg::with_name(OPT_REGEX_SIZE_LIMIT)
+             .long(OPT_REGEX_SIZE_LIMIT)
+             .takes_value(true)
+             .value_name("bytes")
+             .help(format!("{}\n{}",
+                           "Fails with an error on --match regexes whose compiled form exceeds <bytes>,",
+                           "guarding against huge patterns from untrusted input.").as_str()))
+        .arg(Arg::with_name(OPT_REGEX_DFA_SIZE_LIMIT)
+             .long(OPT_REGEX_DFA_SIZE_LIMIT)
+             .takes_value(true)
+             .value_name("bytes")
+             .help(format!("{}\n{}",
+                           "Bounds the cache each --match regex builds while matching to <bytes>.",
+                           "Regexes exceeding it get slower instead of using more memory.").as_str()))
+        .arg(Arg::with_name(OPT_HEADER)
+             .long(OPT_HEADER)
+             .help(format!("{}\n{}\n{}",
+                           "Treats the first row as header so that columns may be referred to by",
+                           "name in --match and all options taking columns.  It isn't matched, and",
+                           "duplicate names are resolved according to --duplicate-names.").as_str()))
+        .arg(Arg::with_name(OPT_DUPLICATE_NAMES)
+             .long(OPT_DUPLICATE_NAMES)
+             .takes_value(true)
+             .value_name("policy")
+             .possible_values(&["first", "last", "number", "reject"])
+             .requires(OPT_HEADER)
+             .help(format!("{}
+{}
+{}",
+                           "Which column a --header name shared by several columns refers to: the",
+                           "first (the default) or the last one, or the first one while the later ones",
+                           "are named like name_2, name_3 (number).  With reject, that's an error.").as_str()))
+        .arg(Arg::with_name(OPT_WITH_HEADER)
+             .long(OPT_WITH_HEADER)
+             .help(format!("{}\n{}",
+                           "Prints the header row before the matching rows using the <disp_cols>",
+                           "of the first --match expression.").as_str()))
+        .arg(Arg::with_name(OPT_RENAME)
+             .long(OPT_RENAME)
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .value_name("col=name,...")
+             .help(format!("{}\n{}",
+                           "Prints the --header names of the columns <col> as the given names, e.g.,",
+                           "with --with-header or as JSON keys.  --match expressions use the read ones.").as_str()))
+        .arg(Arg::with_name(OPT_INVERT_MATCH)
+             .short("v")
+             .long(OPT_INVERT_MATCH)
+             .help(format!("{}\n{}",
+                           "Selects the rows matched by none of the --match expressions.  They are",
+                           "displayed with the <disp_cols> of the first expression.").as_str()))
+        .arg(Arg::with_name(OPT_MATCH_ALL)
+             .long(OPT_MATCH_ALL)
+             .alias("and")
+             .help(format!("{}\n{}",
+                           "Selects the rows matched by all --match expressions instead of any.  They",
+                           "are displayed once with the <disp_cols> of the first expression.").as_str()))
+        .arg(Arg::with_name(OPT_UNION_SELECT)
+             .long(OPT_UNION_SELECT)
+             .conflicts_with(OPT_ONLY_MATCHING)
+             .help(format!("{}\n{}",
+                           "Displays a row matched by several expressions with the union of their",
+                           "<disp_cols>, each column once in the order of the expressions.").as_str()))
+        .arg(Arg::with_name(OPT_EXPLAIN)
+             .long(OPT_EXPLAIN)
+             .help(format!("{}\n{}\n{}",
+                           "Prints the clauses and display selection of each --match expression to",
+                           "stderr and exits without reading input but the --header of the first file,",
+                           "whose names the expressions may use.").as_str()))
+        .arg(Arg::with_name(OPT_NO_QUOTING)
+             .long(OPT_NO_QUOTING)
+             .help(format!("{}\n{}",
+                           "Splits at every separator instead of treating cells enclosed in double",
+                           "quotes as one cell with \"\" as an escaped quote (RFC 4180).").as_str()))
+        .arg(Arg::with_name(OPT_COLLAPSE_SEPARATORS)
+             .long(OPT_COLLAPSE_SEPARATORS)
+             .alias("squeeze")
+             .help(format!("{}\n{}",
+                           "Treats runs of separators as one and ignores leading and trailing ones, like",
+                           "awk's field splitting.  Quoted empty cells like \"\" are kept.").as_str()))
+        .arg(Arg::with_name(OPT_SKIP_BLANK)
+             .long(OPT_SKIP_BLANK)
+             .help("Ignores empty lines, and with --trim also lines consisting of whitespace."))
+        .arg(Arg::with_name(OPT_COMMENT_CHAR)
+             .long(OPT_COMMENT_CHAR)
+             .takes_value(true)
+             .value_name("char")
+             .help(format!("{}\n{}",
+                           "Ignores lines starting with <char>, e.g., # for comments.  A <char> later",
+                           "in a line is no comment.").as_str()))
+        .arg(Arg::with_name(OPT_NULL_DATA)
+             .short("z")
+             .long(OPT_NULL_DATA)
+             .help(format!("{}\n{}",
+                           "Reads and prints records terminated by NUL instead of newline, so cells",
+                           "may contain line breaks, e.g., for find -print0 output.").as_str()))
+        .arg(Arg::with_name(OPT_ENCODING)
+             .long(OPT_ENCODING)
+             .takes_value(true)
+             .value_name("name")
+             .possible_values(&["utf8", "latin1", "windows-1252"])
+             .help(format!("{}\n{}",
+                           "Decodes the input from the given encoding (default: utf8).  The output is",
+                           "always UTF-8.").as_str()))
+        .arg(Arg::with_name(OPT_LOSSY)
+             .long(OPT_LOSSY)
+             .help("Replaces invalid UTF-8 in the input with U+FFFD instead of failing."))
+        .arg(Arg::with_name(OPT_KEEP_BOM)
+             .long(OPT_KEEP_BOM)
+             .help("Keeps a leading UTF-8 byte order mark as part of the first cell."))
+        .arg(Arg::with_name(OPT_FLATTEN)
+             .long(OPT_FLATTEN)
+             .takes_value(true)
+             .value_name("sub-sep")
+             .help(format!("{}\n{}\n{}",
+                           "Prints one row per <sub-sep>-separated element of a displayed cell.",
+                           "A row with several multi-valued displayed cells is reported on stderr and",
+                           "printed as is.").as_str()))
+        .arg(Arg::with_name(OPT_INDEX_BASE)
+             .long(OPT_INDEX_BASE)
+             .takes_value(true)
+             .value_name("n")
+             .help(format!("{}\n{}",
+                           "Starts the printed (i) cell labels at <n>, e.g., 1 for 1-based labels.",
+                           "Only affects printing, not how --match columns are read (default: 0).").as_str()))
+        .arg(Arg::with_name(OPT_ONE_BASED)
+             .long(OPT_ONE_BASED)
+             .conflicts_with(OPT_INDEX_BASE)
+             .help(format!("{}\n{}",
+                           "Counts columns from 1 in all options taking columns and in the printed",
+                           "(i) labels and column numbers, so 1=foo matches the first column.").as_str()))
+        .arg(Arg::with_name(OPT_INDEX_BY_SELECTION)
+             .long(OPT_INDEX_BY_SELECTION)
+             .help("Labels printed cells by their position in <disp_cols> instead of their column."))
+        .arg(Arg::with_name(OPT_NO_INDEX)
+             .long(OPT_NO_INDEX)
+             .help(format!("{}\n{}",
+                           "Prints only the cells joined by the output separator, without (i) labels.",
+                           "Missing cells are printed as empty fields unless --missing-cell is given.").as_str()))
+        .arg(Arg::with_name(OPT_NO_TRAILING_SEPARATOR)
+             .long(OPT_NO_TRAILING_SEPARATOR)
+             .help("Prints the separator only between the cells, not after the last one."))
+        .arg(Arg::with_name(OPT_MISSING_CELL)
+             .long(OPT_MISSING_CELL)
+             .takes_value(true)
+             .value_name("mode")
+             .possible_values(&["placeholder", "empty", "skip"])
+             .help(format!("{}\n{}\n{}\n{}",
+                           "How displayed columns beyond the end of a row are printed: as <no col N>",
+                           "(placeholder), as empty field or JSON null (empty), or not at all (skip).",
+                           "Defaults to placeholder for the pretty output with (i) labels and to empty",
+                           "otherwise.").as_str()))
+        .arg(Arg::with_name(OPT_MAX_CELL_WIDTH)
+             .long(OPT_MAX_CELL_WIDTH)
+             .takes_value(true)
+             .value_name("n")
+             .help(format!("{}\n{}",
+                           "Cuts printed cells after <n> chars and appends \u{2026}.  Matching still uses",
+                           "the whole cells.").as_str()))
+        .arg(Arg::with_name(OPT_SANITIZE_CELLS)
+             .long(OPT_SANITIZE_CELLS)
+             .takes_value(true)
+             .value_name("str")
+             .help(format!("{}\n{}\n{}\n{}",
+                           "Replaces the output separator inside printed cells with <str> so that",
+                           "csv, tsv, and --no-index output needs no quoting for it and can be split",
+                           "naively.  Applies to all output formats and the --template but",
+                           "ndjson-matches, whose match ranges refer to the cells as read.").as_str()))
+        .arg(Arg::with_name(OPT_LIMIT_PER_GROUP)
+             .long(OPT_LIMIT_PER_GROUP)
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .value_name("col:n")
+             .help(format!("{}\n{}",
+                           "Prints at most <n> matching rows for each distinct value of column <col>.",
+                           "Memory grows with the number of distinct values.").as_str()))
+        .arg(Arg::with_name(OPT_DESCRIBE_INPUT)
+             .long(OPT_DESCRIBE_INPUT)
+             .help(format!("{}\n{}",
+                           "Prints the separator and the column count of the first 1000 rows as",
+                           "JSON and exits without matching.").as_str()))
+        .arg(Arg::with_name(OPT_INSPECT)
+             .long(OPT_INSPECT)
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("k")
+             .conflicts_with(OPT_DESCRIBE_INPUT)
+             .help(format!("{}\n{}",
+                           "Reads only the first <k> records (default: 1) and prints the separator,",
+                           "their column count, and the --header names.  Exits without matching.").as_str()))
+        .arg(Arg::with_name(OPT_ON_CHANGE)
+             .long(OPT_ON_CHANGE)
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .value_name("col")
+             .help(format!("{}\n{}",
+                           "Prints a matching row only if its value in column <col> differs from",
+                           "the one of the previously printed row, like uniq on that column.").as_str()))
+        .arg(Arg::with_name(OPT_PROFILE)
+             .long(OPT_PROFILE)
+             .help(format!("{}\n{}\n{}",
+                           "Instead of the matching rows, prints each column's index, its --header",
+                           "name if any, its fill rate, and a sample non-empty value over the matching",
+                           "rows.").as_str()))
+        .arg(Arg::with_name(OPT_AGG)
+             .long(OPT_AGG)
+             .takes_value(true)
+             .value_name("kind:col")
+             .multiple(true)
+             .number_of_values(1)
+             .allow_hyphen_values(true)
+             .conflicts_with_all(&[OPT_PROFILE, OPT_COUNT, OPT_COUNT_NONMATCHING, OPT_REQUIRE])
+             .help(format!("{}\n{}\n{}",
+                           "Instead of the matching rows, prints the sum, min, max, avg, or count of",
+                           "the numbers in column <col> of the matching rows, e.g., sum:3, or the count",
+                           "of its distinct values.  May be given several times.").as_str()))
+        .arg(Arg::with_name(OPT_GROUP_BY)
+             .long(OPT_GROUP_BY)
+             .takes_value(true)
+             .value_name("cols")
+             .allow_hyphen_values(true)
+             .requires(OPT_AGG)
+             .help(format!("{}\n{}\n{}",
+                           "Prints the --agg results for each distinct combination of the cells in the",
+                           "comma-separated <cols>, sorted by these cells.  Memory grows with the",
+                           "number of combinations, and missing cells count as empty.").as_str()))
+        .arg(Arg::with_name(OPT_SORT_BY_COUNT)
+             .long(OPT_SORT_BY_COUNT)
+             .requires(OPT_GROUP_BY)
+             .help(format!("{}\n{}",
+                           "Prints the --group-by groups with the most rows first instead of sorted",
+                           "by their cells, which only order groups with the same number of rows.").as_str()))
+        .arg(Arg::with_name(OPT_REVERSE_COUNT)
+             .long(OPT_REVERSE_COUNT)
+             .requires(OPT_SORT_BY_COUNT)
+             .help("Makes --sort-by-count print the groups with the fewest rows first."))
+        .arg(Arg::with_name(OPT_VALUE_COUNTS)
+             .long(OPT_VALUE_COUNTS)
+             .takes_value(true)
+             .value_name("col")
+             .allow_hyphen_values(true)
+             .conflicts_with_all(&[OPT_PROFILE, OPT_COUNT, OPT_COUNT_NONMATCHING, OPT_REQUIRE,
+                                   OPT_AGG])
+             .help(format!("{}\n{}\n{}",
+                           "Instead of the matching rows, prints each distinct value of column <col>",
+                           "in them and its count as <value><TAB><count>, the most frequent first.",
+                           "Rows without the column aren't counted.").as_str()))
+        .arg(Arg::with_name(OPT_STATS)
+             .long(OPT_STATS)
+             .help(format!("{}\n{}\n{}",
+                           "After each input, prints the number of rows read and selected, the min,",
+                           "max, and number of distinct cell counts of the selected rows, and each",
+                           "column's fill rate among them to stderr.").as_str()))
+        .arg(Arg::with_name(OPT_SUMMARY)
+             .long(OPT_SUMMARY)
+             .help("After each input, prints the number of selected and read rows to stderr."))
+        .arg(Arg::with_name(OPT_PROGRESS)
+             .long(OPT_PROGRESS)
+             .help(format!("{}\n{}",
+                           "Shows the number of rows read and selected so far on a line of stderr",
+                           "which is updated every 100000 rows.  Does nothing if stderr is no terminal.").as_str()))
+        .arg(Arg::with_name(OPT_BUFFER_SIZE)
+             .long(OPT_BUFFER_SIZE)
+             .takes_value(true)
+             .value_name("bytes")
+             .help(format!("{}\n{}",
+                           "Sets the size of the input read buffer (default: 65536).  Larger buffers",
+                           "mean fewer read syscalls on big files at the cost of memory.").as_str()))
+        .arg(Arg::with_name(OPT_JOBS)
+             .short("j")
+             .long(OPT_JOBS)
+             .takes_value(true)
+             .value_name("n")
+             .help(format!("{}\n{}",
+                           "Matches the rows on <n> threads (default: 1).  The output keeps the input",
+                           "order, so this only pays off for big inputs and expensive expressions.").as_str()))
+        .arg(Arg::with_name(OPT_NO_DECOMPRESS)
+             .long(OPT_NO_DECOMPRESS)
+             .help(format!("{}\n{}",
+                           "Reads inputs named *.gz or starting with the gzip magic bytes as is instead",
+                           "of decompressing them (including concatenated members like gzip -dc).").as_str()))
+        .arg(Arg::with_name(OPT_FOLLOW)
+             .long(OPT_FOLLOW)
+             .conflicts_with_all(&[OPT_COUNT, OPT_COUNT_NONMATCHING, OPT_PROFILE, OPT_AGG,
+                                   OPT_VALUE_COUNTS, OPT_TAIL, OPT_SORT])
+             .help(format!("{}\n{}\n{}",
+                           "Keeps reading the single input file at its end like tail -f, printing the",
+                           "rows appended to it until interrupted.  A truncated or rotated file is",
+                           "read again from its start.").as_str()))
+        .arg(Arg::with_name(OPT_COUNT)
+             .short("c")
+             .long(OPT_COUNT)
+             .conflicts_with(OPT_PROFILE)
+             .help(format!("{}\n{}",
+                           "Instead of the matching rows, prints the number of rows matched by any",
+                           "--match expression (by none with --invert-match).").as_str()))
+        .arg(Arg::with_name(OPT_COUNT_NONMATCHING)
+             .long(OPT_COUNT_NONMATCHING)
+             .conflicts_with(OPT_PROFILE)
+             .help("Instead of the matching rows, prints the number of rows no --match expression matches."))
+        .arg(Arg::with_name(OPT_FILES_WITH_MATCHES)
+             .short("l")
+             .long(OPT_FILES_WITH_MATCHES)
+             .conflicts_with_all(&[OPT_PROFILE, OPT_COUNT, OPT_COUNT_NONMATCHING, OPT_FILES_WITHOUT_MATCH])
+             .help(format!("{}\n{}",
+                           "Instead of the matching rows, prints the name of each input with a row",
+                           "matched by any --match expression.  Reading stops at the first one.").as_str()))
+        .arg(Arg::with_name(OPT_FILES_WITHOUT_MATCH)
+             .short("L")
+             .long(OPT_FILES_WITHOUT_MATCH)
+             .conflicts_with_all(&[OPT_PROFILE, OPT_COUNT, OPT_COUNT_NONMATCHING])
+             .help("Instead of the matching rows, prints the name of each input without a matching row."))
+        .arg(Arg::with_name(OPT_QUIET)
+             .short("q")
+             .long(OPT_QUIET)
+             .conflicts_with_all(&[OPT_PROFILE, OPT_COUNT, OPT_COUNT_NONMATCHING,
+                                   OPT_FILES_WITH_MATCHES, OPT_FILES_WITHOUT_MATCH,
+                                   OPT_AGG, OPT_VALUE_COUNTS, OPT_REQUIRE,
+                                   OPT_CHECK_COLUMNS])
+             .help(format!("{}\n{}",
+                           "Prints nothing and only sets the exit status.  Reading stops at the first",
+                           "selected row unless --summary is given, which is all that's printed then.").as_str()))
+        .arg(Arg::with_name(OPT_REQUIRE)
+             .long(OPT_REQUIRE)
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .value_name("spec")
+             .conflicts_with_all(&[OPT_PROFILE, OPT_COUNT, OPT_COUNT_NONMATCHING])
+             .help(format!("{}\n{}\n{}\n{}",
+                           "Checks the matching rows against <spec> instead of printing them.",
+                           "<spec> is a comma-separated list of <col>[:<type>] where <type> is one of",
+                           "str (default), int, number, date, or email.  Prints one line per missing",
+                           "or invalid cell and exits with status 1 if there was any.").as_str()))
+        .arg(Arg::with_name(OPT_REPLACE)
+             .short("r")
+             .long(OPT_REPLACE)
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("spec")
+             .help(format!("{}\n{}\n{}",
+                           "Rewrites the cells in column <col> of printed rows, given as spec",
+                           "<col>/<regex>/<replacement> where <replacement> may refer to groups as $1.",
+                           "Multiple --replace options apply in order.").as_str()))
+        .arg(Arg::with_name(OPT_CHECK_COLUMNS)
+             .long(OPT_CHECK_COLUMNS)
+             .takes_value(true)
+             .min_values(0)
+             .require_equals(true)
+             .value_name("n")
+             .conflicts_with_all(&[OPT_PROFILE, OPT_COUNT, OPT_COUNT_NONMATCHING])
+             .help(format!("{}\n{}\n{}\n{}",
+                           "Checks that the matching rows have <n> columns instead of printing them.",
+                           "Without =<n>, the header (or first) row gives the expected count.  Prints",
+                           "one line per row with another count and a summary, and exits with status",
+                           "1 if there was any.").as_str()))
+        .arg(Arg::with_name(OPT_STRICT)
+             .long(OPT_STRICT)
+             .help(format!("{}\n{}",
+                           "Exits with status 2 at the first row with another number of columns than",
+                           "the --check-columns <n>, the header, or else the first row.").as_str()))
+        .arg(Arg::with_name(OPT_RECTANGULAR)
+             .long(OPT_RECTANGULAR)
+             .help(format!("{}\n{}\n{}",
+                           "Pads the printed rows with the --fill text or truncates them to the number",
+                           "of columns of the --check-columns <n>, the header, or else the first row,",
+                           "so that downstream tools get a fixed column count even from ragged input.").as_str()))
+        .arg(Arg::with_name(OPT_FILL)
+             .long(OPT_FILL)
+             .takes_value(true)
+             .value_name("text")
+             .requires(OPT_RECTANGULAR)
+             .help("The cell --rectangular pads short rows with (default: empty)."))
+        .arg(Arg::with_name(OPT_TEE)
+             .long(OPT_TEE)
+             .help(format!("{}\n{}",
+                           "Prints the matching rows as usual and writes the result of --count,",
+                           "--count-nonmatching, --profile, --agg, or --value-counts to stderr at the end.").as_str()))
+        .arg(Arg::with_name(OPT_RESUME_FROM_LINE)
+             .long(OPT_RESUME_FROM_LINE)
+             .takes_value(true)
+             .value_name("n")
+             .help(format!("{}\n{}\n{}",
+                           "Starts processing at row <n> of the first input, e.g., to resume an",
+                           "interrupted run.  Rows are numbered like --line-number does, and the ones",
+                           "before it are skipped without decoding lines outside of quoted fields.").as_str()))
+        .arg(Arg::with_name(OPT_OUTPUT)
+             .short("o")
+             .long(OPT_OUTPUT)
+             .takes_value(true)
+             .value_name("file")
+             .help("Writes the results to <file> instead of stdout."))
+        .arg(Arg::with_name(OPT_CONFIG)
+             .long(OPT_CONFIG)
+             .takes_value(true)
+             .value_name("file")
+             .help(format!("{}\n{}\n{}\n{}",
+                           "Reads option defaults from <file> instead of $XDG_CONFIG_HOME/svgrep.toml",
+                           "(default: ~/.config/svgrep.toml) if it exists.  Lines are key = \"string\"",
+                           "for separator and the *-char options, or key = true for trim and header.",
+                           "Options on the command line take precedence.").as_str()))
+        .arg(Arg::with_name(OPT_APPEND)
+             .long(OPT_APPEND)
+             .requires(OPT_OUTPUT)
+             .help(format!("{}\n{}",
+                           "Appends to the --output file instead of truncating it",
+                           "(default: truncate like the shell's >).").as_str()))
+        .arg(Arg::with_name(OPT_LINE_NUMBER)
+             .short("n")
+             .long(OPT_LINE_NUMBER)
+             .help(format!("{}\n{}",
+                           "Prefixes each printed row with its 1-based record number followed by a",
+                           "colon.  With --header, the first row after the header is number 1.").as_str()))
+        .arg(Arg::with_name(OPT_BYTE_OFFSET)
+             .short("b")
+             .long(OPT_BYTE_OFFSET)
+             .help(format!("{}\n{}",
+                           "Prefixes each printed row with the 0-based byte offset of its record in the",
+                           "input followed by a colon, after the --line-number if given.").as_str()))
+        .arg(Arg::with_name(OPT_MAX_COUNT)
+             .short("M")
+             .long(OPT_MAX_COUNT)
+             .takes_value(true)
+             .value_name("n")
+             .help(format!("{}\n{}",
+                           "Stops reading an input after <n> selected rows.  A row matched by several",
+                           "--match expressions counts once, and --count reports at most <n>.").as_str()))
+        .arg(Arg::with_name(OPT_TAIL)
+             .long(OPT_TAIL)
+             .takes_value(true)
+             .value_name("n")
+             .conflicts_with_all(&[OPT_AFTER_CONTEXT, OPT_BEFORE_CONTEXT, OPT_CONTEXT])
+             .help(format!("{}\n{}",
+                           "Prints only the last <n> selected rows of each input once it is read.",
+                           "Only these rows are kept in memory, and --count still counts all.").as_str()))
+        .arg(Arg::with_name(OPT_EVERY)
+             .long(OPT_EVERY)
+             .takes_value(true)
+             .value_name("n")
+             .help("Selects only the first of each <n> matching rows, e.g., 1 in 1000 with 1000."))
+        .arg(Arg::with_name(OPT_SAMPLE)
+             .long(OPT_SAMPLE)
+             .takes_value(true)
+             .value_name("rate")
+             .help(format!("{}\n{}",
+                           "Selects each matching row with the probability <rate> between 0 and 1,",
+                           "e.g., about 1% of them with 0.01.").as_str()))
+        .arg(Arg::with_name(OPT_SEED)
+             .long(OPT_SEED)
+             .takes_value(true)
+             .value_name("n")
+             .requires(OPT_SAMPLE)
+             .help("Sets the random seed of --sample for reproducible samples (default: the time)."))
+        .arg(Arg::with_name(OPT_JOIN)
+             .long(OPT_JOIN)
+             .takes_value(true)
+             .value_name("file")
+             .help(format!("{}\n{}\n{}\n{}",
+                           "Appends to each row the cells of the rows of <file> with the same key, which",
+                           "is read with the same options.  The rows are joined before matching, so",
+                           "--match expressions see the appended cells, and are numbered as joined.",
+                           "With --header, <file> has one too naming the appended columns.").as_str()))
+        .arg(Arg::with_name(OPT_ON)
+             .long(OPT_ON)
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .value_name("col=col")
+             .requires(OPT_JOIN)
+             .help(format!("{}\n{}\n{}",
+                           "Joins the rows whose cell in the first column equals the one of the --join",
+                           "row in the second, ignoring surrounding whitespace (default: 0=0).  With",
+                           "--header, the second column may be named by the header of the --join file.").as_str()))
+        .arg(Arg::with_name(OPT_JOIN_TYPE)
+             .long(OPT_JOIN_TYPE)
+             .takes_value(true)
+             .value_name("type")
+             .possible_values(&["inner", "left"])
+             .requires(OPT_JOIN)
+             .help(format!("{}\n{}",
+                           "Drops rows without a --join row (inner, the default), or keeps them with",
+                           "empty appended cells (left).").as_str()))
+        .arg(Arg::with_name(OPT_JOIN_DUPLICATES)
+             .long(OPT_JOIN_DUPLICATES)
+             .takes_value(true)
+             .value_name("mode")
+             .possible_values(&["first", "all"])
+             .requires(OPT_JOIN)
+             .help(format!("{}\n{}",
+                           "Joins only the first --join row with a key (first, the default), or each of",
+                           "them giving one row apiece (all).").as_str()))
+        .arg(Arg::with_name(OPT_SORT)
+             .long(OPT_SORT)
+             .takes_value(true)
+             .value_name("col[:desc]")
+             .allow_hyphen_values(true)
+             .conflicts_with_all(&[OPT_AFTER_CONTEXT, OPT_BEFORE_CONTEXT, OPT_CONTEXT])
+             .help(format!("{}\n{}\n{}",
+                           "Prints the selected rows of each input sorted by the cells of column <col>,",
+                           "e.g., -1, which needn't be displayed, descending with :desc.  Missing cells",
+                           "sort as least.  All selected rows of an input are kept in memory.").as_str()))
+        .arg(Arg::with_name(OPT_NUMERIC_SORT)
+             .long(OPT_NUMERIC_SORT)
+             .requires(OPT_SORT)
+             .help("Compares the --sort cells as numbers.  Cells which aren't numbers sort as least."))
+        .arg(Arg::with_name(OPT_FROM_LINE)
+             .long(OPT_FROM_LINE)
+             .takes_value(true)
+             .value_name("n")
+             .help(format!("{}\n{}",
+                           "Ignores the rows before row <n> of each input.  Rows are numbered from 1",
+                           "as with --line-number, i.e., a --header row is not counted.").as_str()))
+        .arg(Arg::with_name(OPT_TO_LINE)
+             .long(OPT_TO_LINE)
+             .takes_value(true)
+             .value_name("n")
+             .help("Stops reading an input after row <n>, numbered like for --from-line."))
+        .arg(Arg::with_name(OPT_AFTER_CONTEXT)
+             .short("A")
+             .long(OPT_AFTER_CONTEXT)
+             .takes_value(true)
+             .value_name("n")
+             .conflicts_with_all(&[OPT_LIMIT_PER_GROUP, OPT_ON_CHANGE])
+             .help(format!("{}\n{}\n{}",
+                           "Prints <n> rows of context after each selected row using the <disp_cols>",
+                           "of the first --match expression.  Non-adjacent groups of rows are",
+                           "separated by a -- line.").as_str()))
+        .arg(Arg::with_name(OPT_BEFORE_CONTEXT)
+             .short("B")
+             .long(OPT_BEFORE_CONTEXT)
+             .takes_value(true)
+             .value_name("n")
+             .conflicts_with_all(&[OPT_LIMIT_PER_GROUP, OPT_ON_CHANGE])
+             .help("Prints <n> rows of context before each selected row like --after-context."))
+        .arg(Arg::with_name(OPT_CONTEXT)
+             .short("C")
+             .long(OPT_CONTEXT)
+             .takes_value(true)
+             .value_name("n")
+             .conflicts_with_all(&[OPT_LIMIT_PER_GROUP, OPT_ON_CHANGE])
+             .help("Prints <n> rows of context before and after each selected row."))
+        .arg(Arg::with_name(OPT_ONLY_MATCHING)
+             .long(OPT_ONLY_MATCHING)
+             .conflicts_with(OPT_INVERT_MATCH)
+             .help(format!("{}\n{}",
+                           "Displays only the cells matched by a <regex> of the expression instead of",
+                           "its <disp_cols>, and no row if no regex matched any cell.").as_str()))
+        .arg(Arg::with_name(OPT_COLOR)
+             .long(OPT_COLOR)
+             .takes_value(true)
+             .value_name("when")
+             .possible_values(&["auto", "always", "never"])
+             .help(format!("{}\n{}",
+                           "Highlights the <regex> matches in cells of the pretty output: auto (the",
+                           "default) does so if standard output is a terminal.").as_str()))
+        .arg(Arg::with_name(OPT_OUTPUT_FORMAT)
+             .long(OPT_OUTPUT_FORMAT)
+             .takes_value(true)
+             .value_name("format")
+             .possible_values(&["pretty", "csv", "tsv", "json", "table", "ndjson-matches"])
+             .help(format!("{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+                           "Prints the displayed cells with (i) labels (pretty, the default), or as",
+                           "separated values using the --output-separator (csv) or tabs (tsv),",
+                           "quoting cells containing the separator, quotes, or line breaks.  With json,",
+                           "each row is one line holding an object keyed by the --header names or else",
+                           "an array of strings, without file name or line number prefixes.  With",
+                           "table, the cells are padded into aligned columns, which means all rows of",
+                           "an input are kept in memory until its end.  With ndjson-matches, each row",
+                           "is one JSON object with the file name (if printed), the row number, all",
+                           "cells as read, and the {col, start, end} byte ranges matched by regexes.").as_str()))
+        .arg(Arg::with_name(OPT_UNIQUE)
+             .short("u")
+             .long(OPT_UNIQUE)
+             // The counts are of the matching rows, not of the distinct printed ones.
+             .conflicts_with_all(&[OPT_COUNT, OPT_COUNT_NONMATCHING])
+             .help(format!("{}\n{}",
+                           "Prints each distinct row of displayed cells only at its first occurrence.",
+                           "Memory grows with the number of distinct printed rows.").as_str()))
+        .arg(Arg::with_name(OPT_JSON_ARRAY)
+             .long(OPT_JSON_ARRAY)
+             .requires(OPT_OUTPUT_FORMAT)
+             .help("Wraps the rows of --output-format json in a single top-level JSON array."))
+        .arg(Arg::with_name(OPT_TEMPLATE)
+             .long(OPT_TEMPLATE)
+             .takes_value(true)
+             .value_name("str")
+             .conflicts_with(OPT_OUTPUT_FORMAT)
+             .help(format!("{}\n{}\n{}\n{}\n{}",
+                           "Prints each row as <str> with every {<col>} replaced by the cell in <col>,",
+                           "e.g., '{0} -> {2} ({5})', instead of the <disp_cols>.  <col> may be a name",
+                           "with --header.  {{ and }} print literal braces, missing cells are printed",
+                           "empty unless --missing-cell is given, and --sanitize-cells applies to the",
+                           "replaced cells.").as_str()))
+        .arg(Arg::with_name(OPT_WITH_FILENAME)
+             .short("H")
+             .long(OPT_WITH_FILENAME)
+             .overrides_with(OPT_NO_FILENAME)
+             .help(format!("{}\n{}",
+                           "Prefixes each output line with the name of its input file followed by",
+                           "a colon (default with more than one FILE).").as_str()))
+        .arg(Arg::with_name(OPT_NO_FILENAME)
+             .long(OPT_NO_FILENAME)
+             .overrides_with(OPT_WITH_FILENAME)
+             .help("Never prefixes output lines with the input file name."))
+        .get_matches_from_safe(match_hyphen_values(env::args()))
+        .unwrap_or_else(|e| {
+            if e.use_stderr() {
+                eprintln!("{}", e.message);
+                exit(2);
+            }
+            e.exit()
+        })
 }
@@ -0,0 +1,75 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Runs the svgrep binary with args on input.
+fn svgrep(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_svgrep"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The input may not be read to its end.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn exit_status_follows_grep() {
+    assert_eq!(svgrep(&["-m", "0=a"], "a;b\n").status.code(), Some(0));
+    assert_eq!(svgrep(&["-m", "0=x"], "a;b\n").status.code(), Some(1));
+    assert_eq!(svgrep(&["-m", "0=("], "a;b\n").status.code(), Some(2));
+    let missing = svgrep(&["-m", "0=a", "--", "test/no-such-file.csv"], "");
+    assert_eq!(missing.status.code(), Some(2));
+}
+
+#[test]
+fn match_file_adds_an_expression_per_line() {
+    let mut exps = String::from("# The even numbers\n\n");
+    for i in 0..50 {
+        exps.push_str(&format!("0=^{}$\n", 2 * i));
+    }
+    exps.push_str("  # The end\n");
+    let path = std::env::temp_dir().join(format!("svgrep-match-file-{}", std::process::id()));
+    std::fs::write(&path, exps).unwrap();
+    let input: String = (95..105).map(|i| format!("{}\n", i)).collect();
+    let output = svgrep(&["-f", path.to_str().unwrap(), "-m", "0=^101$"], &input);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(0) 96 \n(0) 98 \n(0) 101 \n"
+    );
+}
+
+#[test]
+fn any_separator_wins_over_the_separator_and_must_not_be_empty() {
+    let output = svgrep(
+        &["--any-separator", ",;", "-s", "|", "-m", "2=c"],
+        "a,b;c\n",
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(0) a (1) b (2) c \n"
+    );
+    let output = svgrep(&["--any-separator", "", "-m", "0=a"], "a\n");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn strict_exits_with_status_2_on_ragged_rows() {
+    let output = svgrep(&["--strict", "-m", "0=."], "a;b\nc\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Row 2 has 1 columns instead of 2!"));
+}
+
+#[test]
+fn inspect_needs_no_match_expression() {
+    let output = svgrep(&["--inspect"], "a;b;c\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("columns: 3\n"));
+}